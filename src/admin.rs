@@ -0,0 +1,631 @@
+use std::{io, path::Path};
+
+use axum::{
+    Json, Router,
+    extract::{FromRequest, Query, Request, State},
+    http::{StatusCode, header::CONTENT_TYPE},
+    response::IntoResponse,
+    routing::{get, post},
+};
+use serde::Serialize;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::UnixStream,
+};
+
+use crate::{
+    domains::{
+        repo::RawRepo,
+        volume::{Status as VolumeStatus, Volume},
+    },
+    driver::router::{CreateRequest, DriverError, DriverJson, Empty, ListResponse, Named},
+    plugin::{Error as PluginError, Plugin},
+};
+
+pub const EXPORT: &str = "/admin/export";
+pub const REFRESH: &str = "/Gitvol.Refresh";
+pub const PRUNE: &str = "/Gitvol.Prune";
+pub const LIST: &str = "/Gitvol.List";
+pub const VALIDATE: &str = "/Gitvol.Validate";
+pub const UPDATE: &str = "/Gitvol.Update";
+pub const INSPECT: &str = "/Gitvol.Inspect";
+pub const METRICS: &str = "/metrics";
+pub const HEALTHZ: &str = "/healthz";
+pub const READYZ: &str = "/readyz";
+
+/// Code of a request the admin routes can not make sense of.
+pub const INVALID_REQUEST: &str = "INVALID_REQUEST";
+
+/// Error of the admin routes: the docker shape with a stable `Code` tooling can match on,
+/// see `plugin::Error::code`. The docker routes keep the bare `DriverError`.
+#[cfg_attr(test, derive(Debug, PartialEq, serde::Deserialize))]
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct AdminError {
+    pub err: String,
+    pub code: String,
+}
+
+impl AdminError {
+    fn invalid_request(err: String) -> Self {
+        Self {
+            err,
+            code: INVALID_REQUEST.to_string(),
+        }
+    }
+}
+
+impl From<PluginError> for AdminError {
+    fn from(error: PluginError) -> Self {
+        Self {
+            err: error.to_string(),
+            code: error.code().to_string(),
+        }
+    }
+}
+
+impl IntoResponse for AdminError {
+    fn into_response(self) -> axum::response::Response {
+        Json(self).into_response()
+    }
+}
+
+/// `DriverJson` answering a malformed body with an `AdminError`.
+struct AdminJson<T>(T);
+
+impl<T, S> FromRequest<S> for AdminJson<T>
+where
+    DriverJson<T>: FromRequest<S, Rejection = DriverError>,
+    S: Send + Sync,
+{
+    type Rejection = AdminError;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        DriverJson::from_request(request, state)
+            .await
+            .map(|DriverJson(value)| Self(value))
+            .map_err(|DriverError { err }| AdminError::invalid_request(err))
+    }
+}
+
+#[cfg_attr(test, derive(Debug, PartialEq, serde::Deserialize))]
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Refreshed {
+    pub commit: String,
+}
+
+#[cfg_attr(test, derive(Debug, PartialEq, serde::Deserialize))]
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Pruned {
+    pub removed: usize,
+}
+
+#[derive(serde::Deserialize)]
+struct ListQuery {
+    status: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ValidateRequest {
+    opts: RawRepo,
+    /// Also asks the remote for the requested ref, off by default since it needs network.
+    #[serde(default)]
+    check_remote: bool,
+}
+
+#[cfg_attr(test, derive(Debug, PartialEq, serde::Deserialize))]
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Validated {
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    pub remote_checked: bool,
+}
+
+#[cfg_attr(test, derive(Debug, PartialEq, serde::Deserialize))]
+#[derive(Serialize)]
+pub struct Health {
+    pub git: String,
+    pub git_path: String,
+    pub volumes: usize,
+}
+
+#[cfg_attr(test, derive(Debug, PartialEq, serde::Deserialize))]
+#[derive(Serialize)]
+pub struct Ready {
+    pub git: String,
+}
+
+async fn export_handler(State(plugin): State<Plugin>) -> impl IntoResponse {
+    println!("[DEBUG: {}] :: Request", EXPORT);
+    ([(CONTENT_TYPE, "application/yaml")], plugin.export().await)
+}
+
+async fn metrics_handler(State(plugin): State<Plugin>) -> impl IntoResponse {
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        plugin.metrics().await,
+    )
+}
+
+async fn healthz_handler(State(plugin): State<Plugin>) -> Json<Health> {
+    Json(Health {
+        git: plugin.git_version().to_string(),
+        git_path: plugin.git_path().to_string(),
+        volumes: plugin.volume_count().await,
+    })
+}
+
+async fn readyz_handler(State(plugin): State<Plugin>) -> Result<Json<Ready>, impl IntoResponse> {
+    match plugin.check_git().await {
+        Ok(git) => Ok(Json(Ready { git })),
+        Err(e) => {
+            let error = AdminError::from(e);
+            println!("[ERROR: {}] :: Failed: {}", READYZ, error.err);
+            Err((StatusCode::SERVICE_UNAVAILABLE, error))
+        }
+    }
+}
+
+async fn refresh_handler(
+    State(plugin): State<Plugin>,
+    AdminJson(Named { name }): AdminJson<Named>,
+) -> Result<Json<Refreshed>, AdminError> {
+    println!("[DEBUG: {}] :: Request: volume_name={}", REFRESH, name);
+    plugin
+        .refresh(&name)
+        .await
+        .map(|commit| Json(Refreshed { commit }))
+        .map_err(|e| {
+            let error = AdminError::from(e);
+            println!(
+                "[ERROR: {}] :: Failed: {}. volume_name={}",
+                REFRESH, error.err, name
+            );
+            error
+        })
+}
+
+async fn prune_handler(State(plugin): State<Plugin>) -> Result<Json<Pruned>, AdminError> {
+    println!("[DEBUG: {}] :: Request", PRUNE);
+    plugin
+        .prune()
+        .await
+        .map(|removed| Json(Pruned { removed }))
+        .map_err(|e| {
+            let error = AdminError::from(e);
+            println!("[ERROR: {}] :: Failed: {}", PRUNE, error.err);
+            error
+        })
+}
+
+async fn list_handler(
+    State(plugin): State<Plugin>,
+    Query(ListQuery { status }): Query<ListQuery>,
+) -> Result<Json<ListResponse>, AdminError> {
+    println!("[DEBUG: {}] :: Request: status={:?}", LIST, status);
+    let status = status.map(|status| status.to_lowercase());
+    if let Some(status) = &status
+        && !VolumeStatus::NAMES.contains(&status.as_str())
+    {
+        let err = format!(
+            "Unknown status {status:?}, expected one of {}",
+            VolumeStatus::NAMES.join(", ")
+        );
+        println!("[ERROR: {}] :: Failed: {}", LIST, err);
+        return Err(AdminError::invalid_request(err));
+    }
+    let volumes = plugin.list_by_status(status.as_deref()).await;
+    Ok(Json(ListResponse { volumes }))
+}
+
+async fn validate_handler(
+    State(plugin): State<Plugin>,
+    AdminJson(ValidateRequest { opts, check_remote }): AdminJson<ValidateRequest>,
+) -> Result<Json<Validated>, AdminError> {
+    println!(
+        "[DEBUG: {}] :: Request: check_remote={}",
+        VALIDATE, check_remote
+    );
+    plugin
+        .validate(opts, check_remote)
+        .await
+        .map(|repo| {
+            Json(Validated {
+                url: repo.to_string(),
+                branch: repo.branch,
+                remote_checked: check_remote,
+            })
+        })
+        .map_err(|e| {
+            let error = AdminError::from(e);
+            println!("[ERROR: {}] :: Failed: {}", VALIDATE, error.err);
+            error
+        })
+}
+
+async fn update_handler(
+    State(plugin): State<Plugin>,
+    AdminJson(CreateRequest { name, opts }): AdminJson<CreateRequest<RawRepo>>,
+) -> Result<Json<Empty>, AdminError> {
+    println!("[DEBUG: {}] :: Request: volume_name={}", UPDATE, name);
+    let result = match opts {
+        Some(opts) => plugin.update(&name, opts).await.map_err(AdminError::from),
+        None => Err(AdminError::invalid_request("Opts are required".to_string())),
+    };
+    result.map(|_| Json(Empty {})).inspect_err(|error| {
+        println!(
+            "[ERROR: {}] :: Failed: {}. volume_name={}",
+            UPDATE, error.err, name
+        );
+    })
+}
+
+async fn inspect_handler(
+    State(plugin): State<Plugin>,
+    AdminJson(Named { name }): AdminJson<Named>,
+) -> Result<Json<Volume>, (StatusCode, AdminError)> {
+    println!("[DEBUG: {}] :: Request: volume_name={}", INSPECT, name);
+    plugin.inspect(&name).await.map(Json).map_err(|e| {
+        let error = AdminError::from(e);
+        println!(
+            "[ERROR: {}] :: Failed: {}. volume_name={}",
+            INSPECT, error.err, name
+        );
+        (StatusCode::NOT_FOUND, error)
+    })
+}
+
+pub fn create_router(plugin: Plugin) -> Router {
+    Router::new()
+        .route(EXPORT, get(export_handler))
+        .route(REFRESH, post(refresh_handler))
+        .route(PRUNE, post(prune_handler))
+        .route(LIST, get(list_handler))
+        .route(VALIDATE, post(validate_handler))
+        .route(UPDATE, post(update_handler))
+        .route(INSPECT, post(inspect_handler))
+        .route(METRICS, get(metrics_handler))
+        .route(HEALTHZ, get(healthz_handler))
+        .route(READYZ, get(readyz_handler))
+        .with_state(plugin)
+}
+
+/// Minimal HTTP/1.1 client used by the `export` subcommand to query a running plugin.
+pub async fn request_export(socket: &Path) -> io::Result<String> {
+    let mut stream = UnixStream::connect(socket).await?;
+    let request = format!("GET {EXPORT} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+    stream.write_all(request.as_bytes()).await?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await?;
+
+    let Some((head, body)) = response.split_once("\r\n\r\n") else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "malformed HTTP response",
+        ));
+    };
+    let status = head.lines().next().unwrap_or_default();
+    if !status.contains(" 200 ") {
+        return Err(io::Error::other(format!("unexpected response: {status}")));
+    }
+
+    Ok(body.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        driver::Driver,
+        services::git::{
+            Git,
+            test_mocks::{TestRepo, stub_git},
+        },
+    };
+    use axum_test::TestServer;
+    use serde_json::json;
+    use serde_yaml::Value;
+    use tokio::net::UnixListener;
+
+    const VOLUME: &str = "updated";
+
+    async fn plugin() -> Plugin {
+        Plugin::stub()
+            .await
+            .with_volume("first", RawRepo::stub())
+            .await
+            .with_volume(
+                "second",
+                RawRepo {
+                    tag: Some("v1".into()),
+                    ..RawRepo::stub()
+                },
+            )
+            .await
+    }
+
+    #[tokio::test]
+    async fn export_route() {
+        let server = TestServer::new(create_router(plugin().await)).unwrap();
+
+        let response = server.get(EXPORT).await;
+        response.assert_status_ok();
+
+        let parsed: Value = serde_yaml::from_str(&response.text()).unwrap();
+        let volumes = parsed["volumes"].as_mapping().unwrap();
+        assert_eq!(volumes.len(), 2);
+        assert_eq!(parsed["volumes"]["second"]["driver_opts"]["branch"], "v1");
+    }
+
+    #[tokio::test]
+    async fn refresh_route() {
+        let test_repo = TestRepo::new();
+        let plugin = Plugin::temp()
+            .await
+            .with_temp_volume(
+                "refetched",
+                test_repo.create_raw_repo(None, None, Some(true)),
+            )
+            .await
+            .with_temp_volume("static", test_repo.create_raw_repo(None, None, None))
+            .await;
+        let mountpoint = plugin.mount("refetched", "id-1").await.unwrap();
+        plugin.mount("static", "id-2").await.unwrap();
+        let server = TestServer::new(create_router(plugin.clone())).unwrap();
+
+        test_repo.change("master", "changed value");
+        let response = server
+            .post(REFRESH)
+            .json(&json!({ "Name": "refetched" }))
+            .await;
+        response.assert_status_ok();
+        response.assert_json(&Refreshed {
+            commit: test_repo.head("master"),
+        });
+        TestRepo::test_is_changed(&mountpoint, "master", "changed value");
+
+        for (name, code) in [
+            ("static", "NOT_REFETCHABLE"),
+            ("missing", "VOLUME_NOT_FOUND"),
+        ] {
+            let response = server.post(REFRESH).json(&json!({ "Name": name })).await;
+            response.assert_status_ok();
+            let error: AdminError = response.json();
+            assert!(error.err.contains(name), "{error:?}");
+            assert_eq!(error.code, code);
+        }
+    }
+
+    #[tokio::test]
+    async fn inspect_route() {
+        let test_repo = TestRepo::new();
+        let plugin = Plugin::temp()
+            .await
+            .with_temp_volume(
+                VOLUME,
+                RawRepo {
+                    token: Some("s3cr3t-token".into()),
+                    ..test_repo.create_raw_repo(None, None, None)
+                },
+            )
+            .await;
+        let mountpoint = plugin.mount(VOLUME, "id-1").await.unwrap();
+        let server = TestServer::new(create_router(plugin.clone())).unwrap();
+
+        let response = server.post(INSPECT).json(&json!({ "Name": VOLUME })).await;
+        response.assert_status_ok();
+        assert!(!response.text().contains("s3cr3t"));
+        let volume: Volume = response.json();
+        assert_eq!(volume.name, VOLUME);
+        assert_eq!(volume.path, Some(mountpoint));
+        assert_eq!(volume.commit, Some(test_repo.head("master")));
+        assert_eq!(volume.default_branch.as_deref(), Some("master"));
+        assert_eq!(volume.status, VolumeStatus::Clonned);
+        assert!(volume.containers.contains("id-1"));
+        assert!(volume.repo.auth.is_some());
+
+        let response = server
+            .post(INSPECT)
+            .json(&json!({ "Name": "missing" }))
+            .await;
+        response.assert_status(StatusCode::NOT_FOUND);
+        assert_eq!(response.json::<AdminError>().code, "VOLUME_NOT_FOUND");
+    }
+
+    #[tokio::test]
+    async fn update_route() {
+        let test_repo = TestRepo::new().with_branch("release");
+        let plugin = Plugin::temp()
+            .await
+            .with_temp_volume(VOLUME, test_repo.create_raw_repo(None, None, None))
+            .await;
+        let first = plugin.mount(VOLUME, "id").await.unwrap();
+        TestRepo::test_is_branch(&first, "master");
+        let server = TestServer::new(create_router(plugin.clone())).unwrap();
+        let url = test_repo.create_raw_repo(None, None, None).url.unwrap();
+        let request = json!({ "Name": VOLUME, "Opts": { "url": url, "branch": "release" } });
+
+        let error: AdminError = server.post(UPDATE).json(&request).await.json();
+        assert!(error.err.contains("in use"), "{error:?}");
+        assert_eq!(error.code, "IN_USE");
+
+        plugin.unmount(VOLUME, "id").await.unwrap();
+        let response = server.post(UPDATE).json(&request).await;
+        response.assert_status_ok();
+        response.assert_json(&json!({}));
+        assert!(!first.exists());
+
+        let second = plugin.mount(VOLUME, "id").await.unwrap();
+        TestRepo::test_is_branch(&second, "release");
+        let info = plugin.get(VOLUME).await.unwrap().unwrap();
+        assert_eq!(info.status.commit, Some(test_repo.head("release")));
+    }
+
+    #[tokio::test]
+    async fn prune_route() {
+        let test_repo = TestRepo::new();
+        let plugin = Plugin::temp()
+            .await
+            .with_temp_volume("unmounted", test_repo.create_raw_repo(None, None, None))
+            .await
+            .with_temp_volume("mounted", test_repo.create_raw_repo(None, None, None))
+            .await;
+        let cleared = plugin.mount("unmounted", "id-1").await.unwrap();
+        plugin.unmount("unmounted", "id-1").await.unwrap();
+        let mounted = plugin.mount("mounted", "id-2").await.unwrap();
+        let volume_dir = cleared.parent().unwrap().to_path_buf();
+        assert!(volume_dir.exists());
+        let server = TestServer::new(create_router(plugin.clone())).unwrap();
+
+        let response = server.post(PRUNE).await;
+        response.assert_status_ok();
+        response.assert_json(&Pruned { removed: 1 });
+
+        assert!(plugin.get("unmounted").await.unwrap().is_none());
+        assert!(!volume_dir.exists());
+        assert!(mounted.exists());
+        server.post(PRUNE).await.assert_json(&Pruned { removed: 0 });
+    }
+
+    #[tokio::test]
+    async fn list_route() {
+        let test_repo = TestRepo::new();
+        let plugin = Plugin::temp()
+            .await
+            .with_temp_volume("created", test_repo.create_raw_repo(None, None, None))
+            .await
+            .with_temp_volume("mounted", test_repo.create_raw_repo(None, None, None))
+            .await;
+        plugin.mount("mounted", "id").await.unwrap();
+        let server = TestServer::new(create_router(plugin.clone())).unwrap();
+
+        let response = server.get(LIST).add_query_param("status", "Clonned").await;
+        response.assert_status_ok();
+        let list: serde_json::Value = response.json();
+        let volumes = list["Volumes"].as_array().unwrap();
+        assert_eq!(volumes.len(), 1);
+        assert_eq!(volumes[0]["Name"], "mounted");
+
+        let list: serde_json::Value = server.get(LIST).await.json();
+        assert_eq!(list["Volumes"].as_array().unwrap().len(), 2);
+
+        let error: AdminError = server
+            .get(LIST)
+            .add_query_param("status", "gone")
+            .await
+            .json();
+        assert!(error.err.contains("gone"), "{error:?}");
+        assert_eq!(error.code, INVALID_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn validate_route() {
+        let test_repo = TestRepo::new();
+        let url = test_repo.path().to_str().unwrap().to_string();
+        let plugin = Plugin::temp().await;
+        let server = TestServer::new(create_router(plugin.clone())).unwrap();
+
+        let response = server
+            .post(VALIDATE)
+            .json(&json!({ "Opts": { "url": url, "branch": "master" }, "CheckRemote": true }))
+            .await;
+        response.assert_status_ok();
+        response.assert_json(&Validated {
+            url: url.clone(),
+            branch: Some("master".into()),
+            remote_checked: true,
+        });
+
+        let error: AdminError = server
+            .post(VALIDATE)
+            .json(&json!({ "Opts": { "url": url, "tag": "missing" }, "CheckRemote": true }))
+            .await
+            .json();
+        assert!(error.err.contains("missing"), "{error:?}");
+        assert_eq!(error.code, "REF_NOT_FOUND");
+
+        let error: AdminError = server
+            .post(VALIDATE)
+            .json(&json!({ "Opts": { "url": "ftp://example.com/repo.git" } }))
+            .await
+            .json();
+        assert!(error.err.contains("ftp"), "{error:?}");
+        assert_eq!(error.code, "INVALID_OPTIONS");
+
+        let error: AdminError = server.post(VALIDATE).text("{").await.json();
+        assert_eq!(error.code, INVALID_REQUEST);
+
+        plugin.test_is_empty_list().await;
+    }
+
+    #[tokio::test]
+    async fn metrics_route() {
+        let server = TestServer::new(create_router(plugin().await)).unwrap();
+
+        let response = server.get(METRICS).await;
+        response.assert_status_ok();
+        response.assert_header(CONTENT_TYPE, "text/plain; version=0.0.4");
+        assert!(response.text().contains("gitvol_volumes_active 0\n"));
+    }
+
+    #[tokio::test]
+    async fn healthz_route() {
+        let plugin = plugin().await;
+        let version = plugin.git_version().to_string();
+        let git_path = plugin.git_path().to_string();
+        let server = TestServer::new(create_router(plugin)).unwrap();
+
+        let response = server.get(HEALTHZ).await;
+        response.assert_status_ok();
+        response.assert_json(&Health {
+            git: version,
+            git_path,
+            volumes: 2,
+        });
+    }
+
+    #[tokio::test]
+    async fn readyz_route() {
+        let temp = tempfile::tempdir().unwrap();
+        let bin = stub_git(temp.path(), "echo 'git version 9.9.9-stub'");
+        let git = Git::init(Some(&bin)).await.unwrap();
+        let server = TestServer::new(create_router(Plugin::new(temp.path(), git))).unwrap();
+
+        let response = server.get(READYZ).await;
+        response.assert_status_ok();
+        response.assert_json(&Ready {
+            git: "git version 9.9.9-stub".into(),
+        });
+
+        // The image lost its git executable after the plugin started.
+        std::fs::write(&bin, "#!/bin/sh\nexit 127\n").unwrap();
+        let response = server.get(READYZ).await;
+        response.assert_status(StatusCode::SERVICE_UNAVAILABLE);
+        let error: AdminError = response.json();
+        assert!(!error.err.is_empty());
+        assert!(!error.code.is_empty());
+    }
+
+    #[tokio::test]
+    async fn export_over_socket() {
+        let temp = tempfile::tempdir().unwrap();
+        let socket = temp.path().join("plugin.sock");
+        let listener = UnixListener::bind(&socket).unwrap();
+        let app = plugin()
+            .await
+            .into_router()
+            .merge(create_router(plugin().await));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let yaml = request_export(&socket).await.unwrap();
+
+        let parsed: Value = serde_yaml::from_str(&yaml).unwrap();
+        assert!(parsed["volumes"]["first"].is_mapping());
+    }
+}
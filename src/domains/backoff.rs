@@ -0,0 +1,114 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Splitmix64 generator for retry delays. Not suitable for anything secret, only to keep
+/// retries of many volumes from hitting a remote at the same moment.
+pub struct Jitter(u64);
+
+impl Jitter {
+    #[cfg(test)]
+    pub fn seeded(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    /// Seeded from the clock and a counter, two calls in the same instant still differ.
+    pub fn from_clock() -> Self {
+        static CALLS: AtomicU64 = AtomicU64::new(0);
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        Self(nanos ^ CALLS.fetch_add(1, Ordering::Relaxed).rotate_left(32))
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Full jitter: uniform in `[0, ceiling(attempt, base, max)]`.
+    pub fn next_delay(&mut self, attempt: u32, base: Duration, max: Duration) -> Duration {
+        let ceiling = ceiling(attempt, base, max).as_nanos() as u64;
+        if ceiling == 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_nanos(self.next_u64() % (ceiling + 1))
+    }
+}
+
+/// `base * 2^attempt`, never more than `max`.
+pub fn ceiling(attempt: u32, base: Duration, max: Duration) -> Duration {
+    2u32.checked_pow(attempt)
+        .and_then(|factor| base.checked_mul(factor))
+        .map_or(max, |delay| delay.min(max))
+}
+
+/// Delay before retry number `attempt`, counted from 0.
+pub fn next_delay(attempt: u32, base: Duration, max: Duration) -> Duration {
+    Jitter::from_clock().next_delay(attempt, base, max)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    const BASE: Duration = Duration::from_millis(100);
+    const MAX: Duration = Duration::from_secs(10);
+
+    #[rstest]
+    #[case(0, BASE)]
+    #[case(3, Duration::from_millis(800))]
+    #[case(7, MAX)]
+    #[case(40, MAX)]
+    fn ceiling_doubles_up_to_max(#[case] attempt: u32, #[case] expect: Duration) {
+        assert_eq!(ceiling(attempt, BASE, MAX), expect);
+    }
+
+    #[test]
+    fn delays_stay_within_ceiling() {
+        let mut jitter = Jitter::seeded(7);
+        for attempt in 0..12 {
+            let ceiling = ceiling(attempt, BASE, MAX);
+            for _ in 0..200 {
+                let delay = jitter.next_delay(attempt, BASE, MAX);
+                assert!(delay <= ceiling, "{delay:?} > {ceiling:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn delays_grow_across_attempts() {
+        let mut jitter = Jitter::seeded(42);
+        let mean = |jitter: &mut Jitter, attempt| {
+            (0..500)
+                .map(|_| jitter.next_delay(attempt, BASE, MAX))
+                .sum::<Duration>()
+                / 500
+        };
+
+        let means: Vec<Duration> = (0..7).map(|attempt| mean(&mut jitter, attempt)).collect();
+        assert!(means.windows(2).all(|w| w[0] < w[1]), "{means:?}");
+    }
+
+    #[test]
+    fn seeded_is_deterministic() {
+        let delays = |seed| {
+            let mut jitter = Jitter::seeded(seed);
+            (0..5)
+                .map(|attempt| jitter.next_delay(attempt, BASE, MAX))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(delays(1), delays(1));
+        assert_ne!(delays(1), delays(2));
+    }
+
+    #[test]
+    fn zero_base_has_no_delay() {
+        assert_eq!(next_delay(5, Duration::ZERO, MAX), Duration::ZERO);
+    }
+}
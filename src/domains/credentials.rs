@@ -0,0 +1,200 @@
+use std::{
+    fmt::{Debug, Display},
+    hash::Hash,
+};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use serde::{Deserialize, Serialize, Serializer};
+
+#[derive(Debug, thiserror::Error)]
+#[cfg_attr(test, derive(PartialEq))]
+pub enum Error {
+    #[error("Only one of token or username/password credentials is allowed")]
+    Ambiguous,
+
+    #[error("Username and password must be provided together")]
+    Incomplete,
+}
+
+/// Stands in for a secret wherever it is shown or serialized.
+const REDACTED: &str = "***";
+
+/// A secret value that never shows up in `Debug`/`Display` output. It is serialized as
+/// `***`, so the state file does not keep it: a volume loaded from there has lost it.
+#[derive(Clone, PartialEq, Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    /// Whether this is the placeholder read back from the state file.
+    pub fn is_redacted(&self) -> bool {
+        self.0 == REDACTED
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(REDACTED)
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(value: &str) -> Self {
+        Self(value.to_string())
+    }
+}
+
+impl From<String> for Secret {
+    fn from(value: String) -> Self {
+        Self(value)
+    }
+}
+
+impl Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("***")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Credentials {
+    Token(Secret),
+    Basic { username: String, password: Secret },
+}
+
+impl Credentials {
+    pub fn from_parts(
+        token: Option<Secret>,
+        username: Option<String>,
+        password: Option<Secret>,
+    ) -> Result<Option<Self>, Error> {
+        match (token, username, password) {
+            (None, None, None) => Ok(None),
+            (Some(token), None, None) => Ok(Some(Self::Token(token))),
+            (Some(_), _, _) => Err(Error::Ambiguous),
+            (None, Some(username), Some(password)) => Ok(Some(Self::Basic { username, password })),
+            (None, _, _) => Err(Error::Incomplete),
+        }
+    }
+
+    /// Copy with the secret masked, for showing the credentials of a volume.
+    pub fn masked(&self) -> Self {
+        match self {
+            Self::Token(_) => Self::Token(Secret::from(REDACTED)),
+            Self::Basic { username, .. } => Self::Basic {
+                username: username.clone(),
+                password: Secret::from(REDACTED),
+            },
+        }
+    }
+
+    /// Read back from the state file, the secret is gone and has to be given again.
+    pub fn is_redacted(&self) -> bool {
+        match self {
+            Self::Token(token) => token.is_redacted(),
+            Self::Basic { password, .. } => password.is_redacted(),
+        }
+    }
+
+    /// Value for `http.extraHeader`, so the secret never has to be part of the clone URL.
+    pub fn http_header(&self) -> String {
+        match self {
+            Self::Token(token) => format!("Authorization: Bearer {}", token.expose()),
+            Self::Basic { username, password } => {
+                let encoded = STANDARD.encode(format!("{username}:{}", password.expose()));
+                format!("Authorization: Basic {encoded}")
+            }
+        }
+    }
+}
+
+impl Display for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Token(_) => f.write_str("token(***)"),
+            Self::Basic { username, .. } => write!(f, "basic({username}:***)"),
+        }
+    }
+}
+
+// Credentials do not define which repository is cloned, so they stay out of the volume identity.
+impl Hash for Credentials {
+    fn hash<H: std::hash::Hasher>(&self, _state: &mut H) {}
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    const SECRET: &str = "s3cr3t-token";
+
+    #[rstest]
+    #[case(None, None, None, None)]
+    #[case(Some(SECRET), None, None, Some(Credentials::Token(SECRET.into())))]
+    #[case(None, Some("user"), Some(SECRET), Some(Credentials::Basic { username: "user".into(), password: SECRET.into() }))]
+    fn valid_parts(
+        #[case] token: Option<&str>,
+        #[case] username: Option<&str>,
+        #[case] password: Option<&str>,
+        #[case] expect: Option<Credentials>,
+    ) {
+        let result = Credentials::from_parts(
+            token.map(Into::into),
+            username.map(Into::into),
+            password.map(Into::into),
+        )
+        .unwrap();
+        assert_eq!(result, expect);
+    }
+
+    #[rstest]
+    #[case(Some(SECRET), Some("user"), None, Error::Ambiguous)]
+    #[case(Some(SECRET), None, Some(SECRET), Error::Ambiguous)]
+    #[case(None, Some("user"), None, Error::Incomplete)]
+    #[case(None, None, Some(SECRET), Error::Incomplete)]
+    fn invalid_parts(
+        #[case] token: Option<&str>,
+        #[case] username: Option<&str>,
+        #[case] password: Option<&str>,
+        #[case] expect: Error,
+    ) {
+        let error = Credentials::from_parts(
+            token.map(Into::into),
+            username.map(Into::into),
+            password.map(Into::into),
+        )
+        .unwrap_err();
+        assert_eq!(error, expect);
+    }
+
+    #[test]
+    fn basic_header() {
+        let credentials = Credentials::Basic {
+            username: "user".into(),
+            password: "pass".into(),
+        };
+        assert_eq!(
+            credentials.http_header(),
+            "Authorization: Basic dXNlcjpwYXNz"
+        );
+    }
+
+    #[rstest]
+    #[case(Credentials::Token(SECRET.into()))]
+    #[case(Credentials::Basic { username: "user".into(), password: SECRET.into() })]
+    fn redacted_output(#[case] credentials: Credentials) {
+        assert!(!credentials.to_string().contains(SECRET));
+        assert!(!format!("{credentials:?}").contains(SECRET));
+
+        let json = serde_json::to_string(&credentials).unwrap();
+        assert!(!json.contains(SECRET), "{json}");
+        let loaded: Credentials = serde_json::from_str(&json).unwrap();
+        assert!(loaded.is_redacted());
+        assert!(!credentials.is_redacted());
+    }
+}
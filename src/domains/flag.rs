@@ -0,0 +1,65 @@
+use serde::{Deserialize, Deserializer, de::Error};
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawFlag {
+    Bool(bool),
+    Str(String),
+}
+
+/// Deserializes an optional boolean option that may arrive either as a JSON boolean
+/// (direct API callers) or as a string (`docker volume create -o key=value`).
+pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<bool>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let Some(raw) = Option::<RawFlag>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+
+    match raw {
+        RawFlag::Bool(value) => Ok(Some(value)),
+        RawFlag::Str(value) => match value.trim().to_lowercase().as_str() {
+            "true" => Ok(Some(true)),
+            "false" => Ok(Some(false)),
+            _ => Err(D::Error::custom(format!(
+                "invalid boolean value {value:?}, expected \"true\" or \"false\""
+            ))),
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rstest::rstest;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize)]
+    struct Opts {
+        #[serde(default, deserialize_with = "super::deserialize")]
+        flag: Option<bool>,
+    }
+
+    #[rstest]
+    #[case(r#"{"flag": true}"#, Some(true))]
+    #[case(r#"{"flag": "true"}"#, Some(true))]
+    #[case(r#"{"flag": " TRUE "}"#, Some(true))]
+    #[case(r#"{"flag": false}"#, Some(false))]
+    #[case(r#"{"flag": "false"}"#, Some(false))]
+    #[case(r#"{"flag": null}"#, None)]
+    #[case(r#"{}"#, None)]
+    fn valid_flag(#[case] json: &str, #[case] expect: Option<bool>) {
+        let opts: Opts = serde_json::from_str(json).unwrap();
+        assert_eq!(opts.flag, expect);
+    }
+
+    #[rstest]
+    #[case(r#"{"flag": "Tratata"}"#)]
+    #[case(r#"{"flag": ""}"#)]
+    #[case(r#"{"flag": 1}"#)]
+    #[case(r#"{"flag": ["true"]}"#)]
+    fn invalid_flag(#[case] json: &str) {
+        let result = serde_json::from_str::<Opts>(json);
+        assert!(result.is_err(), "Successed parsing {json}");
+    }
+}
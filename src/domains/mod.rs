@@ -1,4 +1,12 @@
+pub mod backoff;
 pub mod cmd;
+pub mod compose;
+pub mod credentials;
+pub mod flag;
+pub mod proxy;
+pub mod ready;
 pub mod repo;
+pub mod stable_hash;
+pub mod timestamp;
 pub mod url;
 pub mod volume;
@@ -0,0 +1,209 @@
+use std::{
+    path::{Component, Path, PathBuf},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::time::{Instant, sleep};
+use tokio_util::sync::CancellationToken;
+
+use super::cmd::Cmd;
+
+const COMMAND_PREFIX: &str = "cmd:";
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+#[cfg_attr(test, derive(PartialEq))]
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("ready check can not be empty")]
+    Empty,
+
+    #[error("ready check path {0:?} must be relative to the repository root")]
+    OutsidePath(String),
+}
+
+/// Condition a freshly cloned volume must satisfy before the mount succeeds.
+/// `cmd:<command>` runs a shell command in the checkout, anything else is a file path
+/// relative to the repository root that has to exist.
+#[cfg_attr(test, derive(Debug))]
+#[derive(Hash, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ReadyCheck {
+    File(PathBuf),
+    Command(String),
+}
+
+impl TryFrom<String> for ReadyCheck {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let value = value.trim();
+
+        if let Some(command) = value.strip_prefix(COMMAND_PREFIX) {
+            let command = command.trim();
+            if command.is_empty() {
+                return Err(Error::Empty);
+            }
+            return Ok(Self::Command(command.to_string()));
+        }
+
+        if value.is_empty() {
+            return Err(Error::Empty);
+        }
+
+        let path = PathBuf::from(value);
+        let escapes = path
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir));
+        if escapes {
+            return Err(Error::OutsidePath(value.to_string()));
+        }
+
+        Ok(Self::File(path))
+    }
+}
+
+impl std::fmt::Display for ReadyCheck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::File(path) => write!(f, "file {path:?}"),
+            Self::Command(command) => write!(f, "command {command:?}"),
+        }
+    }
+}
+
+/// Whether the `ready_check` option runs a command, which is only allowed with hooks.
+pub fn is_command(option: &str) -> bool {
+    option.trim_start().starts_with(COMMAND_PREFIX)
+}
+
+impl ReadyCheck {
+    /// The check in the form accepted by the `ready_check` volume option.
+    pub fn to_option(&self) -> String {
+        match self {
+            Self::File(path) => path.display().to_string(),
+            Self::Command(command) => format!("{COMMAND_PREFIX}{command}"),
+        }
+    }
+
+    async fn passes(&self, dir: &Path, timeout: Duration, cancel: &CancellationToken) -> bool {
+        match self {
+            Self::File(path) => dir.join(path).exists(),
+            Self::Command(command) => Cmd::new("sh")
+                .arg("-c")
+                .arg(command)
+                .current_dir(dir)
+                .timeout(Some(timeout))
+                .cancel_on(cancel)
+                .exec()
+                .await
+                .is_ok(),
+        }
+    }
+
+    /// Polls the check until it passes. Returns `false` when it did not pass within `timeout`
+    /// or `cancel` fired.
+    pub async fn wait(&self, dir: &Path, timeout: Duration, cancel: &CancellationToken) -> bool {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if self.passes(dir, remaining, cancel).await {
+                return true;
+            }
+            if cancel.is_cancelled() || Instant::now() + POLL_INTERVAL > deadline {
+                return false;
+            }
+            tokio::select! {
+                _ = sleep(POLL_INTERVAL) => {}
+                _ = cancel.cancelled() => return false,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("dist/index.html", ReadyCheck::File("dist/index.html".into()))]
+    #[case(" ./ready ", ReadyCheck::File("./ready".into()))]
+    #[case("cmd: test -f ready", ReadyCheck::Command("test -f ready".into()))]
+    fn parse(#[case] value: &str, #[case] expect: ReadyCheck) {
+        let check = ReadyCheck::try_from(value.to_string()).unwrap();
+        assert_eq!(check, expect);
+    }
+
+    #[rstest]
+    #[case("", Error::Empty)]
+    #[case("cmd:  ", Error::Empty)]
+    #[case("../etc/passwd", Error::OutsidePath("../etc/passwd".into()))]
+    #[case("/etc/passwd", Error::OutsidePath("/etc/passwd".into()))]
+    fn parse_invalid(#[case] value: &str, #[case] expect: Error) {
+        let error = ReadyCheck::try_from(value.to_string()).unwrap_err();
+        assert_eq!(error, expect);
+    }
+
+    #[tokio::test]
+    async fn wait_for_file() {
+        let temp = tempfile::tempdir().unwrap();
+        let check = ReadyCheck::File("ready".into());
+        let ready = temp.path().join("ready");
+
+        tokio::spawn(async move {
+            sleep(Duration::from_millis(300)).await;
+            tokio::fs::write(ready, "").await.unwrap();
+        });
+
+        let cancel = CancellationToken::new();
+        assert!(
+            check
+                .wait(temp.path(), Duration::from_secs(5), &cancel)
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn wait_timeout() {
+        let temp = tempfile::tempdir().unwrap();
+
+        let cancel = CancellationToken::new();
+
+        let file = ReadyCheck::File("ready".into());
+        assert!(
+            !file
+                .wait(temp.path(), Duration::from_millis(300), &cancel)
+                .await
+        );
+
+        let command = ReadyCheck::Command("exit 1".into());
+        assert!(
+            !command
+                .wait(temp.path(), Duration::from_millis(300), &cancel)
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn wait_cancelled() {
+        let temp = tempfile::tempdir().unwrap();
+        let cancel = CancellationToken::new();
+        let command = ReadyCheck::Command("sleep 10".into());
+
+        tokio::spawn({
+            let cancel = cancel.clone();
+            async move {
+                sleep(Duration::from_millis(200)).await;
+                cancel.cancel();
+            }
+        });
+
+        let started = Instant::now();
+        assert!(
+            !command
+                .wait(temp.path(), Duration::from_secs(30), &cancel)
+                .await
+        );
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
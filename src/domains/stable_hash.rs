@@ -0,0 +1,85 @@
+use std::hash::{Hash, Hasher};
+
+use sha2::{Digest, Sha256};
+
+/// Hex characters kept from the digest, 128 bits are plenty for directory names.
+const HEX_LEN: usize = 32;
+
+/// SHA-256 behind the `Hasher` interface, so derived `Hash` impls can feed it.
+///
+/// Unlike `DefaultHasher` its output does not depend on the Rust version or the platform:
+/// integers are written as little-endian 64-bit values whatever their native width.
+pub struct StableHasher(Sha256);
+
+impl StableHasher {
+    pub fn new() -> Self {
+        Self(Sha256::new())
+    }
+
+    pub fn hex(self) -> String {
+        let digest = self.0.finalize();
+        let mut hex: String = digest.iter().map(|byte| format!("{byte:02x}")).collect();
+        hex.truncate(HEX_LEN);
+        hex
+    }
+}
+
+impl Hasher for StableHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.update(bytes);
+    }
+
+    fn write_u8(&mut self, i: u8) {
+        self.write_u64(i.into());
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.write_u64(i.into());
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write_u64(i.into());
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.0.update(i.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_i64(&mut self, i: i64) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_isize(&mut self, i: isize) {
+        self.write_i64(i as i64);
+    }
+
+    /// Only needed by the `Hasher` interface, `hex` is the actual result.
+    fn finish(&self) -> u64 {
+        let digest = self.0.clone().finalize();
+        u64::from_le_bytes(digest[..8].try_into().unwrap_or_default())
+    }
+}
+
+/// Stable hex hash of `value`.
+pub fn hex<T: Hash + ?Sized>(value: &T) -> String {
+    let mut hasher = StableHasher::new();
+    value.hash(&mut hasher);
+    hasher.hex()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn known_values() {
+        // A str hashes as its bytes and a 0xff terminator, widened to 64 bits here.
+        assert_eq!(hex(""), "159754eeb1b8e153388279fd7cdf7c32");
+        assert_eq!(hex(&("name", 1u8)), hex(&("name", 1u64)));
+        assert_ne!(hex(&("ab", "c")), hex(&("a", "bc")));
+    }
+}
@@ -2,13 +2,21 @@ use std::{
     collections::HashSet,
     hash::{DefaultHasher, Hash, Hasher},
     path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
 };
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 use crate::domains::repo::RawRepo;
 
-use super::repo::Repo;
+use super::{repo::Repo, stable_hash};
+
+pub const DATA_DIR: &str = "data";
+pub const CHECKOUTS_DIR: &str = "checkouts";
+/// Hash characters a readable directory name keeps.
+const SHORT_HASH_LEN: usize = 12;
+/// Characters of the volume name a readable directory name keeps.
+const MAX_NAME_LEN: usize = 64;
 
 #[cfg_attr(test, derive(PartialEq))]
 #[derive(Debug, thiserror::Error)]
@@ -23,22 +31,61 @@ pub enum Error {
     Repo(#[from] super::repo::Error),
 }
 
+/// How the directories of new volumes are named under the base path.
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum)]
+pub enum DirNaming {
+    /// The hash of the volume name and options
+    #[default]
+    Hash,
+    /// `<name>-<short hash>`, easier to find on the host
+    Readable,
+}
+
 #[cfg_attr(test, derive(PartialEq))]
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Status {
     Created,
+    Cloning,
     Clonned,
     Cleared,
+    Failed { reason: String },
 }
 
 #[cfg_attr(test, derive(Debug, PartialEq))]
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Volume {
     pub name: String,
     pub path: Option<PathBuf>,
+    pub archive: Option<PathBuf>,
+    pub commit: Option<String>,
+    /// Branch the remote HEAD pointed to when a repo without a branch was cloned.
+    #[serde(default)]
+    pub default_branch: Option<String>,
+    /// `repo.branch` named a tag when cloned. Tags do not move, such volumes are not refetched.
+    #[serde(default)]
+    pub tag: bool,
     pub repo: Repo,
     pub status: Status,
     pub containers: HashSet<String>,
+    /// Name of the volume directory when it is not the hash, see `DirNaming`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dir_name: Option<String>,
+    /// Base directory its `storage_class` resolved to, so the clone is still found once the
+    /// class is no longer configured.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data_base: Option<PathBuf>,
+    // State files written before the timestamps were added are read with the load time.
+    #[serde(default = "SystemTime::now")]
+    pub created_at: SystemTime,
+    #[serde(default = "SystemTime::now")]
+    pub updated_at: SystemTime,
+    #[serde(skip)]
+    pub last_refetch: Option<Instant>,
+    /// Periodic refetches failed in a row, the next one waits for `refetch_retry_at`.
+    #[serde(skip)]
+    pub refetch_failures: u32,
+    #[serde(skip)]
+    pub refetch_retry_at: Option<Instant>,
 }
 
 impl TryFrom<(&str, RawRepo)> for Volume {
@@ -52,13 +99,25 @@ impl TryFrom<(&str, RawRepo)> for Volume {
         }
 
         let repo = Repo::try_from(raw)?;
+        let now = SystemTime::now();
 
         Ok(Self {
             name: name.to_string(),
             repo,
             path: None,
+            archive: None,
+            commit: None,
+            default_branch: None,
+            tag: false,
             containers: HashSet::new(),
+            dir_name: None,
+            data_base: None,
             status: Status::Created,
+            created_at: now,
+            updated_at: now,
+            last_refetch: None,
+            refetch_failures: 0,
+            refetch_retry_at: None,
         })
     }
 }
@@ -75,17 +134,109 @@ impl TryFrom<(&str, Option<RawRepo>)> for Volume {
     }
 }
 
+impl Status {
+    pub const NAMES: [&str; 5] = ["created", "cloning", "clonned", "cleared", "failed"];
+
+    /// Lowercase name of the state, without the details of `Failed`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Created => "created",
+            Self::Cloning => "cloning",
+            Self::Clonned => "clonned",
+            Self::Cleared => "cleared",
+            Self::Failed { .. } => "failed",
+        }
+    }
+}
+
 impl Volume {
-    pub fn create_path_from(&mut self, base_path: &Path) -> PathBuf {
+    /// Directory holding everything that belongs to the volume: its state file, the clone
+    /// and the archive of a cleared clone.
+    pub fn dir(&self, base_path: &Path) -> PathBuf {
+        match &self.dir_name {
+            Some(dir_name) => base_path.join(dir_name),
+            None => base_path.join(self.hash()),
+        }
+    }
+
+    /// Names the volume directory by `naming`. A readable name keeps a short hash of the
+    /// name and options, so volumes whose names read alike still get their own directory.
+    pub fn with_dir_naming(self, naming: DirNaming) -> Self {
+        let dir_name = match naming {
+            DirNaming::Hash => None,
+            DirNaming::Readable => {
+                let mut name: String = self
+                    .name
+                    .chars()
+                    .take(MAX_NAME_LEN)
+                    .map(|c| match c {
+                        'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-' => c,
+                        _ => '_',
+                    })
+                    .collect();
+                name.push('-');
+                name.push_str(&self.hash()[..SHORT_HASH_LEN]);
+                Some(name)
+            }
+        };
+        Self { dir_name, ..self }
+    }
+
+    /// Stable hash of the name and the options, the name of the volume directory.
+    pub fn hash(&self) -> String {
+        stable_hash::hex(&(&self.name, &self.repo))
+    }
+
+    /// Directory the volume had before the names were derived from a stable hash.
+    pub fn legacy_dir(&self, base_path: &Path) -> PathBuf {
         let mut hasher = DefaultHasher::new();
         hasher.write(self.name.as_bytes());
         hasher.write(b"_");
         self.repo.hash(&mut hasher);
-        let hash_part = hasher.finish();
-        let path = base_path.join(hash_part.to_string());
-        self.path = Some(path.clone());
+        base_path.join(hasher.finish().to_string())
+    }
+
+    /// Where the clone of the volume is placed once mounted.
+    pub fn data_path(&self, base_path: &Path) -> PathBuf {
+        self.dir(base_path).join(DATA_DIR)
+    }
+
+    /// Parent of the per-container checkouts of an `isolated` volume.
+    pub fn checkouts_path(&self, base_path: &Path) -> PathBuf {
+        self.dir(base_path).join(CHECKOUTS_DIR)
+    }
+
+    /// Checkout of a single container of an `isolated` volume.
+    pub fn container_path(&self, base_path: &Path, id: &str) -> PathBuf {
+        self.checkouts_path(base_path).join(stable_hash::hex(id))
+    }
 
-        path
+    /// Every checkout of a mounted volume: the shared clone, or one per container when isolated.
+    pub fn checkouts(&self, base_path: &Path) -> Vec<PathBuf> {
+        if self.repo.isolated {
+            self.containers
+                .iter()
+                .map(|id| self.container_path(base_path, id))
+                .collect()
+        } else {
+            self.path.iter().cloned().collect()
+        }
+    }
+
+    /// What docker mounts: the clone or its `mount_subpath`. An isolated volume has no single
+    /// checkout and reports the parent of its checkouts.
+    pub fn mountpoint(&self) -> Option<PathBuf> {
+        let path = self.path.as_ref()?;
+        Some(if self.repo.isolated {
+            path.clone()
+        } else {
+            self.repo.mountpoint(path)
+        })
+    }
+
+    pub fn is_refetch_due(&self, debounce: Duration) -> bool {
+        self.last_refetch
+            .is_none_or(|last_refetch| last_refetch.elapsed() >= debounce)
     }
 }
 
@@ -145,15 +296,54 @@ pub mod test {
     }
 
     #[test]
-    fn create_path() {
-        let mut volume = Volume::try_from((VOLUME_NAME, RawRepo::stub())).unwrap();
+    fn data_path() {
+        let volume = Volume::try_from((VOLUME_NAME, RawRepo::stub())).unwrap();
 
         assert_eq!(volume.path, None);
 
         let base_path = PathBuf::from("/tmp/test");
-        volume.create_path_from(&base_path);
+        let path = volume.data_path(&base_path);
+
+        assert!(path.starts_with(volume.dir(&base_path)));
+        assert!(path.starts_with(base_path));
+    }
 
-        assert!(matches!(volume.path, Some(p) if p.starts_with(base_path)));
+    #[rstest]
+    #[case(DirNaming::Hash)]
+    #[case(DirNaming::Readable)]
+    fn dir_naming(#[case] naming: DirNaming) {
+        let base_path = PathBuf::from("/tmp/test");
+        let volume = |name: &str, url: &str| {
+            Volume::try_from((name, RawRepo::from_url(url)))
+                .unwrap()
+                .with_dir_naming(naming)
+        };
+        let volumes = [
+            volume("docs", REPO_URL),
+            volume("docs", "https://example.com/other.git"),
+            volume("docs.v1", REPO_URL),
+            volume("docs_v1", REPO_URL),
+            volume("../..", REPO_URL),
+            volume(".hidden", REPO_URL),
+            volume(&"long".repeat(100), REPO_URL),
+        ];
+
+        let dirs: HashSet<PathBuf> = volumes.iter().map(|v| v.dir(&base_path)).collect();
+        assert_eq!(dirs.len(), volumes.len());
+        for volume in &volumes {
+            let dir = volume.dir(&base_path);
+            assert_eq!(dir.parent(), Some(base_path.as_path()));
+            let dir_name = dir.file_name().unwrap().to_str().unwrap();
+            assert!(!dir_name.starts_with('.'), "{dir_name}");
+            assert!(dir_name.len() <= 100, "{dir_name}");
+            assert!(dir_name.contains(&volume.hash()[..SHORT_HASH_LEN]));
+        }
+        if naming == DirNaming::Readable {
+            assert_eq!(
+                volumes[0].dir(&base_path),
+                base_path.join(format!("docs-{}", &volumes[0].hash()[..12]))
+            );
+        }
     }
 
     #[test]
@@ -163,21 +353,123 @@ pub mod test {
         let url3 = format!("{}/some-test", REPO_URL);
         let opts3 = RawRepo::from_url(&url3);
 
-        let mut volume1 = Volume::try_from((VOLUME_NAME, opts1)).unwrap();
-        let mut volume2 = Volume::try_from((VOLUME_NAME, opts2)).unwrap();
-        let mut volume3 = Volume::try_from((VOLUME_NAME, opts3)).unwrap();
+        let volume1 = Volume::try_from((VOLUME_NAME, opts1)).unwrap();
+        let volume2 = Volume::try_from((VOLUME_NAME, opts2)).unwrap();
+        let volume3 = Volume::try_from((VOLUME_NAME, opts3)).unwrap();
 
         let base_path = PathBuf::from("/tmp/test");
-        volume1.create_path_from(&base_path);
-        volume2.create_path_from(&base_path);
-        volume3.create_path_from(&base_path);
-
-        let path1 = volume1.path.unwrap();
-        let path2 = volume2.path.unwrap();
-        let path3 = volume3.path.unwrap();
+        let path1 = volume1.data_path(&base_path);
+        let path2 = volume2.data_path(&base_path);
+        let path3 = volume3.data_path(&base_path);
 
         assert_eq!(path1, path2);
         assert_ne!(path1, path3);
         assert_ne!(path2, path3);
     }
+
+    #[test]
+    fn stable_dir() {
+        let volume = Volume::try_from((VOLUME_NAME, RawRepo::stub())).unwrap();
+
+        assert_eq!(
+            volume.dir(Path::new("/tmp/test")),
+            PathBuf::from("/tmp/test/d2b5141ff64eb44c0e4f0667140ede0e")
+        );
+    }
+
+    #[test]
+    fn equivalent_urls_share_path() {
+        let base_path = PathBuf::from("/tmp/test");
+        let paths: HashSet<_> = [
+            "https://example.com/repo",
+            "https://example.com/repo/",
+            "https://Example.com/repo.git",
+        ]
+        .into_iter()
+        .map(|url| {
+            Volume::try_from((VOLUME_NAME, RawRepo::from_url(url)))
+                .unwrap()
+                .data_path(&base_path)
+        })
+        .collect();
+
+        assert_eq!(paths.len(), 1, "{paths:?}");
+    }
+
+    fn dir_of(raw: RawRepo) -> PathBuf {
+        Volume::try_from((VOLUME_NAME, raw))
+            .unwrap()
+            .dir(Path::new("/tmp/test"))
+    }
+
+    #[rstest]
+    #[case::same_branch(
+        RawRepo { branch: Some("main".into()), ..RawRepo::stub() },
+        RawRepo { branch: Some("main".into()), ..RawRepo::stub() },
+        true
+    )]
+    #[case::other_branch(
+        RawRepo { branch: Some("main".into()), ..RawRepo::stub() },
+        RawRepo { branch: Some("develop".into()), ..RawRepo::stub() },
+        false
+    )]
+    #[case::branch_or_default(
+        RawRepo { branch: Some("main".into()), ..RawRepo::stub() },
+        RawRepo::stub(),
+        false
+    )]
+    #[case::tag(
+        RawRepo { tag: Some("v1".into()), ..RawRepo::stub() },
+        RawRepo { tag: Some("v2".into()), ..RawRepo::stub() },
+        false
+    )]
+    #[case::refetch(
+        RawRepo { refetch: Some(true), ..RawRepo::stub() },
+        RawRepo { refetch: Some(false), ..RawRepo::stub() },
+        false
+    )]
+    #[case::credentials(
+        RawRepo { token: Some("first".into()), ..RawRepo::stub() },
+        RawRepo { token: Some("second".into()), ..RawRepo::stub() },
+        true
+    )]
+    #[case::verify_signature(
+        RawRepo { verify_signature: Some(true), ..RawRepo::stub() },
+        RawRepo::stub(),
+        false
+    )]
+    #[case::verify_signature_unset(
+        RawRepo { verify_signature: Some(false), ..RawRepo::stub() },
+        RawRepo::stub(),
+        true
+    )]
+    #[case::proxy(
+        RawRepo { proxy: Some("http://first:3128".into()), ..RawRepo::stub() },
+        RawRepo { proxy: Some("http://second:3128".into()), ..RawRepo::stub() },
+        false
+    )]
+    #[case::proxy_unset(
+        RawRepo { proxy: Some("http://first:3128".into()), ..RawRepo::stub() },
+        RawRepo::stub(),
+        false
+    )]
+    fn repo_fields_in_path(#[case] first: RawRepo, #[case] second: RawRepo, #[case] same: bool) {
+        assert_eq!(dir_of(first) == dir_of(second), same);
+    }
+
+    #[test]
+    fn bare_changes_path() {
+        let volume = Volume::try_from((VOLUME_NAME, RawRepo::stub())).unwrap();
+        let bare = Volume::try_from((
+            VOLUME_NAME,
+            RawRepo {
+                bare: Some(true),
+                ..RawRepo::stub()
+            },
+        ))
+        .unwrap();
+
+        let base_path = PathBuf::from("/tmp/test");
+        assert_ne!(volume.data_path(&base_path), bare.data_path(&base_path));
+    }
 }
@@ -2,11 +2,11 @@ use std::{fmt::Debug, path::PathBuf};
 
 use axum::Router;
 use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use tokio_util::sync::CancellationToken;
 
-#[cfg_attr(test, derive(Debug, PartialEq, Deserialize))]
-#[derive(Serialize)]
+#[cfg_attr(test, derive(PartialEq))]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, clap::ValueEnum)]
 #[serde(rename_all = "lowercase")]
-#[allow(unused)]
 pub enum Scope {
     Local,
     Global,
@@ -27,14 +27,16 @@ pub struct VolumeInfo<S> {
     pub status: S,
 }
 
+pub const VOLUME_DRIVER: &str = "VolumeDriver";
+
 #[async_trait::async_trait]
 pub trait Driver: Clone + Send + Sync + 'static {
-    type Error: std::error::Error;
+    type Error: std::error::Error + Send + 'static;
     type Status: Serialize;
     type Opts: DeserializeOwned + Debug + Send;
 
     async fn activate(&self) -> Result<Vec<String>, Self::Error> {
-        Ok(vec!["VolumeDriver".to_string()])
+        Ok(vec![VOLUME_DRIVER.to_string()])
     }
 
     async fn capabilities(&self) -> Result<Scope, Self::Error> {
@@ -42,11 +44,21 @@ pub trait Driver: Clone + Send + Sync + 'static {
     }
 
     async fn path(&self, name: &str) -> Result<Option<PathBuf>, Self::Error>;
-    async fn get(&self, name: &str) -> Result<VolumeInfo<Self::Status>, Self::Error>;
+    async fn get(&self, name: &str) -> Result<Option<VolumeInfo<Self::Status>>, Self::Error>;
     async fn list(&self) -> Result<Vec<ItemVolume>, Self::Error>;
     async fn create(&self, name: &str, opts: Option<Self::Opts>) -> Result<(), Self::Error>;
     async fn remove(&self, name: &str) -> Result<(), Self::Error>;
     async fn mount(&self, name: &str, id: &str) -> Result<PathBuf, Self::Error>;
+
+    /// Like `mount`, but gives up once `cancel` fires.
+    async fn mount_cancellable(
+        &self,
+        name: &str,
+        id: &str,
+        _cancel: CancellationToken,
+    ) -> Result<PathBuf, Self::Error> {
+        self.mount(name, id).await
+    }
     async fn unmount(&self, name: &str, id: &str) -> Result<(), Self::Error>;
 
     #[allow(dead_code)]
@@ -55,18 +67,25 @@ pub trait Driver: Clone + Send + Sync + 'static {
     }
 }
 
-mod router {
+pub(crate) mod router {
 
     use super::*;
     use axum::{
         Json, Router,
-        extract::{Request, State},
-        http::{HeaderValue, Uri, header::CONTENT_TYPE},
+        extract::{FromRequest, Request, State, rejection::JsonRejection},
+        http::{
+            HeaderMap, HeaderName, HeaderValue, Method, Uri,
+            header::{ACCEPT, CONTENT_TYPE},
+        },
         middleware::{self, Next},
         response::{IntoResponse, Response},
         routing::post,
     };
     use serde::Serialize;
+    use tracing::{Instrument, info_span};
+    use uuid::Uuid;
+
+    pub const REQUEST_ID: HeaderName = HeaderName::from_static("x-gitvol-request-id");
 
     macro_rules! log_request {
         ($uri:ident, $($arg:tt)+) => {
@@ -108,6 +127,33 @@ mod router {
 
     type Result<T> = std::result::Result<Json<T>, DriverError>;
 
+    /// `Json` extractor answering a malformed body with a `DriverError`: docker reads only
+    /// that shape and reports anything else as a protocol error.
+    pub struct DriverJson<T>(pub T);
+
+    impl<T, S> FromRequest<S> for DriverJson<T>
+    where
+        Json<T>: FromRequest<S, Rejection = JsonRejection>,
+        S: Send + Sync,
+    {
+        type Rejection = DriverError;
+
+        async fn from_request(
+            request: Request,
+            state: &S,
+        ) -> std::result::Result<Self, Self::Rejection> {
+            let uri = request.uri().clone();
+            match Json::<T>::from_request(request, state).await {
+                Ok(Json(value)) => Ok(Self(value)),
+                Err(rejection) => {
+                    let err = rejection.body_text();
+                    println!("[ERROR: {}] :: Failed: {}", uri, err);
+                    Err(DriverError { err })
+                }
+            }
+        }
+    }
+
     #[cfg_attr(test, derive(Debug, PartialEq, Serialize))]
     #[derive(Deserialize)]
     #[serde(rename_all = "PascalCase")]
@@ -171,7 +217,9 @@ mod router {
     #[derive(Serialize)]
     #[serde(rename_all = "PascalCase")]
     pub struct GetResponse<S> {
-        pub volume: FullVolume<S>,
+        /// Left out for a missing volume, docker then reports "no such volume" itself.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub volume: Option<FullVolume<S>>,
     }
 
     #[cfg_attr(test, derive(Debug, PartialEq, Deserialize))]
@@ -225,7 +273,7 @@ mod router {
     async fn path_handler<D: Driver>(
         uri: Uri,
         State(driver): State<D>,
-        Json(Named { name }): Json<Named>,
+        DriverJson(Named { name }): DriverJson<Named>,
     ) -> Result<OptionalMountpoint> {
         log_request!(uri, "volume_name={}", name);
         let result = driver
@@ -238,19 +286,16 @@ mod router {
     async fn get_handler<D: Driver>(
         uri: Uri,
         State(driver): State<D>,
-        Json(Named { name }): Json<Named>,
+        DriverJson(Named { name }): DriverJson<Named>,
     ) -> Result<GetResponse<D::Status>> {
         log_request!(uri, "volume_name={}", name);
-        let result = driver
-            .get(&name)
-            .await
-            .map(|VolumeInfo { mountpoint, status }| GetResponse {
-                volume: FullVolume {
-                    name: name.clone(),
-                    mountpoint,
-                    status,
-                },
-            });
+        let result = driver.get(&name).await.map(|info| GetResponse {
+            volume: info.map(|VolumeInfo { mountpoint, status }| FullVolume {
+                name: name.clone(),
+                mountpoint,
+                status,
+            }),
+        });
         parse_response!(uri, result, "volume_name={}", name)
     }
 
@@ -263,7 +308,7 @@ mod router {
     async fn create_handler<D: Driver>(
         uri: Uri,
         State(driver): State<D>,
-        Json(CreateRequest { name, opts }): Json<CreateRequest<D::Opts>>,
+        DriverJson(CreateRequest { name, opts }): DriverJson<CreateRequest<D::Opts>>,
     ) -> Result<Empty> {
         log_request!(uri, "volume_name={}, create_options={:?}", name, opts);
         let result = driver.create(&name, opts).await.map(|_| Empty {});
@@ -273,7 +318,7 @@ mod router {
     async fn remove_handler<D: Driver>(
         uri: Uri,
         State(driver): State<D>,
-        Json(Named { name }): Json<Named>,
+        DriverJson(Named { name }): DriverJson<Named>,
     ) -> Result<Empty> {
         log_request!(uri, "volume_name={}", name);
         let result = driver.remove(&name).await.map(|_| Empty {});
@@ -283,12 +328,21 @@ mod router {
     async fn mount_handler<D: Driver>(
         uri: Uri,
         State(driver): State<D>,
-        Json(NamedWID { name, id }): Json<NamedWID>,
+        DriverJson(NamedWID { name, id }): DriverJson<NamedWID>,
     ) -> Result<Mountpoint> {
         log_request!(uri, "volume_name={}; id={}", name, id);
-        let result = driver
-            .mount(&name, &id)
+        // Docker drops the connection when it stops waiting for the mount. This handler is
+        // dropped along with it, the guard then cancels the mount, which runs in its own
+        // task so it can still clean up after itself. The task keeps the request span.
+        let cancel = CancellationToken::new();
+        let _guard = cancel.clone().drop_guard();
+        let mount = tokio::spawn({
+            let (name, id) = (name.clone(), id.clone());
+            async move { driver.mount_cancellable(&name, &id, cancel).await }.in_current_span()
+        });
+        let result = mount
             .await
+            .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
             .map(|mountpoint| Mountpoint { mountpoint });
         parse_response!(uri, result, "volume_name={}; id={}", name, id)
     }
@@ -296,7 +350,7 @@ mod router {
     async fn unmount_handler<D: Driver>(
         uri: Uri,
         State(driver): State<D>,
-        Json(NamedWID { name, id }): Json<NamedWID>,
+        DriverJson(NamedWID { name, id }): DriverJson<NamedWID>,
     ) -> Result<Empty> {
         log_request!(uri, "volume_name={}; id={}", name, id);
         let result = driver.unmount(&name, &id).await.map(|_| Empty {});
@@ -321,15 +375,57 @@ mod router {
             .with_state(driver)
     }
 
+    /// Prefix of the media types of the docker plugin protocol, the daemon sends one in `Accept`.
+    const PLUGIN_MEDIA_TYPE: &str = "application/vnd.docker.plugins.";
+
+    fn speaks_plugin_protocol(headers: &HeaderMap) -> bool {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|v: &HeaderValue| v.to_str().ok())
+        };
+        let is_plugin = |value: &str| {
+            value
+                .split(',')
+                .any(|media| media.trim().starts_with(PLUGIN_MEDIA_TYPE))
+        };
+        let content_type = header(CONTENT_TYPE);
+        let json_body = content_type.is_none_or(|value| {
+            let media = value.split(';').next().unwrap_or_default().trim();
+            media == "application/json" || media.starts_with(PLUGIN_MEDIA_TYPE)
+        });
+        json_body && (header(ACCEPT).is_some_and(is_plugin) || content_type.is_some_and(is_plugin))
+    }
+
+    /// Strict mode: rejects calls that do not come from a docker daemon, e.g. a stray curl
+    /// against a TCP listener. Reads are let through for probes and `gitvol export`.
+    pub async fn require_plugin_protocol(request: Request, next: Next) -> Response {
+        if request.method() == Method::GET || speaks_plugin_protocol(request.headers()) {
+            return next.run(request).await;
+        }
+
+        let err = "Request does not use the docker plugin protocol".to_string();
+        println!("[ERROR: {}] :: Failed: {}", request.uri(), err);
+        DriverError { err }.into_response()
+    }
+
     async fn transform_headers(mut request: Request, next: Next) -> Response {
         let headers = request.headers_mut();
         headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
-        let mut response = next.run(request).await;
+
+        // Every event of the request carries the id through the span.
+        let request_id = Uuid::new_v4().to_string();
+        let span = info_span!("call", uri = %request.uri(), request_id);
+        let mut response = next.run(request).instrument(span).await;
+
         let response_headers = response.headers_mut();
         response_headers.append(
             CONTENT_TYPE,
             HeaderValue::from_static("application/vnd.docker.plugin.v1+json"),
         );
+        if let Ok(value) = HeaderValue::from_str(&request_id) {
+            response_headers.insert(REQUEST_ID, value);
+        }
 
         response
     }
@@ -403,6 +499,16 @@ mod test_mocks {
             let server = TestServer::new(app.clone().into_router()).unwrap();
             Server { app, server }
         }
+
+        pub fn into_strict_server() -> Server {
+            let app = Self::new();
+            let router = app
+                .clone()
+                .into_router()
+                .layer(axum::middleware::from_fn(require_plugin_protocol));
+            let server = TestServer::new(router).unwrap();
+            Server { app, server }
+        }
     }
 
     #[async_trait::async_trait]
@@ -418,13 +524,10 @@ mod test_mocks {
             Ok(vol.and_then(|v| v.mountpoint.clone()))
         }
 
-        async fn get(&self, name: &str) -> Result<VolumeInfo<Self::Status>, Self::Error> {
+        async fn get(&self, name: &str) -> Result<Option<VolumeInfo<Self::Status>>, Self::Error> {
             self.check_error().await?;
             let volumes = self.volumes.lock().await;
-            volumes
-                .get(name)
-                .cloned()
-                .ok_or(StrError("not found".into()))
+            Ok(volumes.get(name).cloned())
         }
 
         async fn list(&self) -> Result<Vec<ItemVolume>, Self::Error> {
@@ -464,8 +567,10 @@ mod test_mocks {
         }
 
         async fn mount(&self, name: &str, _id: &str) -> Result<PathBuf, Self::Error> {
+            tracing::info!(volume = name, "Mounting");
             self.check_error().await?;
-            let VolumeInfo { mountpoint, status } = self.get(name).await?;
+            let VolumeInfo { mountpoint, status } =
+                self.get(name).await?.ok_or(StrError("not found".into()))?;
             if let Some(path) = mountpoint {
                 return Ok(path);
             }
@@ -485,7 +590,8 @@ mod test_mocks {
 
         async fn unmount(&self, name: &str, _id: &str) -> Result<(), Self::Error> {
             self.check_error().await?;
-            let VolumeInfo { mountpoint, .. } = self.get(name).await?;
+            let VolumeInfo { mountpoint, .. } =
+                self.get(name).await?.ok_or(StrError("not found".into()))?;
             if mountpoint.is_some() {
                 let mut volumes = self.volumes.lock().await;
                 volumes.insert(
@@ -592,7 +698,9 @@ mod test_mocks {
 
     impl GetResponse<String> {
         fn new(volume: FullVolume<String>) -> Self {
-            Self { volume }
+            Self {
+                volume: Some(volume),
+            }
         }
 
         pub fn stub() -> Self {
@@ -638,6 +746,7 @@ mod tests {
     use super::router::*;
     use super::test_mocks::*;
     use super::*;
+    use crate::split_tracing::test_mocks::LogCapture;
 
     mod first_requests {
         use super::*;
@@ -652,6 +761,36 @@ mod tests {
                 });
         }
 
+        #[tokio::test]
+        async fn request_ids() {
+            let server = Test::into_server();
+
+            let first = server.post("/Plugin.Activate").await;
+            let second = server.post("/Plugin.Activate").await;
+
+            let first = first.header(REQUEST_ID);
+            let second = second.header(REQUEST_ID);
+            assert_eq!(first.len(), 36);
+            assert_ne!(first, second);
+        }
+
+        #[tokio::test]
+        async fn request_id_in_handler_logs() {
+            let (logs, _guard) = LogCapture::start();
+            let server = Test::into_server();
+
+            let response = server.post(MOUNT).json(&NamedWID::stub()).await;
+
+            let request_id = response.header(REQUEST_ID);
+            let request_id = request_id.to_str().unwrap();
+            let lines = logs.lines();
+            let line = lines.iter().find(|line| line.contains("Mounting"));
+            assert!(
+                line.is_some_and(|line| line.contains(request_id)),
+                "{lines:?}"
+            );
+        }
+
         #[tokio::test]
         async fn capabilities() {
             Test::into_server()
@@ -708,7 +847,7 @@ mod tests {
                 .post(GET)
                 .json(&Named::stub())
                 .await
-                .assert_json(&DriverError::new("not found"));
+                .assert_json(&serde_json::json!({}));
         }
 
         #[tokio::test]
@@ -735,6 +874,59 @@ mod tests {
             .assert_json(&DriverError::new("empty options"));
     }
 
+    #[rstest::rstest]
+    #[case::not_json(CREATE, "{not json", "JSON")]
+    #[case::missing_name(CREATE, r#"{"Opts": "def"}"#, "missing field `Name`")]
+    #[case::wrong_type(MOUNT, r#"{"Name": 1, "ID": "id"}"#, "invalid type")]
+    #[tokio::test]
+    async fn malformed_body(#[case] path: &str, #[case] body: &str, #[case] expect: &str) {
+        let response = Test::into_server().post(path).text(body).await;
+
+        response.assert_status_ok();
+        let error: DriverError = response.json();
+        assert!(error.err.contains(expect), "{error:?}");
+    }
+
+    #[rstest::rstest]
+    #[case::docker(Some("application/vnd.docker.plugins.v1.2+json"), None, true)]
+    #[case::docker_json(
+        Some("application/vnd.docker.plugins.v1.2+json"),
+        Some("application/json"),
+        true
+    )]
+    #[case::bogus_type(
+        Some("application/vnd.docker.plugins.v1.2+json"),
+        Some("text/plain"),
+        false
+    )]
+    #[case::plain_json(None, Some("application/json"), false)]
+    #[case::no_headers(None, None, false)]
+    #[tokio::test]
+    async fn strict_protocol(
+        #[case] accept: Option<&str>,
+        #[case] content_type: Option<&str>,
+        #[case] accepted: bool,
+    ) {
+        let server = Test::into_strict_server();
+        let mut request = server.post(LIST).bytes("{}".into());
+        if let Some(accept) = accept {
+            request = request.add_header(axum::http::header::ACCEPT, accept);
+        }
+        if let Some(content_type) = content_type {
+            request = request.content_type(content_type);
+        }
+
+        let response = request.await;
+        if accepted {
+            response.assert_status_ok();
+            response.assert_json(&ListResponse { volumes: vec![] });
+        } else {
+            response.assert_status_ok();
+            let error: DriverError = response.json();
+            assert!(error.err.contains("plugin protocol"), "{error:?}");
+        }
+    }
+
     #[tokio::test]
     async fn failed_created_volume() {
         let server = Test::into_server();
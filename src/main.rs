@@ -1,32 +1,126 @@
+mod admin;
 mod domains;
 mod driver;
 mod macros;
 mod plugin;
+mod server;
 mod services;
 mod settings;
 mod split_tracing;
 
-use axum::serve;
-use tokio::{fs, net::UnixListener};
+use tokio::fs;
 
-use crate::{driver::Driver, plugin::Plugin, services::git::Git, settings::Settings};
+use crate::{
+    domains::url::HostPolicy,
+    driver::Driver,
+    plugin::Plugin,
+    server::{Listener, set_socket_permissions, shutdown_signal},
+    services::{events::Events, git::Git},
+    settings::{Command, Listen, Settings},
+};
+
+const OBJECT_CACHE_DIR: &str = ".objects";
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    split_tracing::init();
+    if let Some((Command::Export, socket)) = Settings::command()? {
+        print!("{}", admin::request_export(&socket).await?);
+        return Ok(());
+    }
 
     let settings = Settings::parse().await?;
 
-    if settings.socket.exists() {
-        fs::remove_file(&settings.socket).await?;
+    split_tracing::init(settings.log_format, &settings.log_level);
+
+    if let Listen::Unix(socket) = &settings.listen
+        && socket.exists()
+    {
+        fs::remove_file(socket).await?;
+    }
+
+    let mut git = Git::init(settings.git_bin.as_deref())
+        .await?
+        .with_env(
+            settings
+                .git_env
+                .iter()
+                .map(|var| (var.key.clone(), var.value.clone()))
+                .collect(),
+        )
+        .with_ssh_agent(settings.ssh_agent.clone())
+        .with_host_key_checking(settings.ssh_host_key_checking)
+        .with_gpg_home(settings.gpg_home.clone())
+        .with_timeout(settings.git_timeout)
+        .with_max_output(settings.max_command_output)
+        .with_repo_config(settings.repo_config)
+        .with_retries(settings.clone_retries, settings.clone_retry_backoff);
+    if settings.object_cache {
+        git = git
+            .with_object_cache(&settings.mount_path.join(OBJECT_CACHE_DIR))
+            .await?;
     }
+    let plugin = Plugin::load(&settings.mount_path, git)
+        .await?
+        .with_refetch_debounce(settings.refetch_debounce)
+        .with_ready_timeout(settings.ready_timeout)
+        .with_compress_cleared(settings.compress_cleared)
+        .with_allow_hooks(settings.allow_hooks)
+        .with_strict_refetch(settings.strict_refetch)
+        .with_share_clones(settings.share_clones)
+        .with_volume_mode(settings.volume_mode)
+        .with_storage_classes(settings.storage_classes.clone())
+        .with_events(
+            settings
+                .events
+                .as_deref()
+                .map(Events::open)
+                .unwrap_or_default(),
+        )
+        .with_scope(settings.scope)
+        .with_implements(settings.implements.clone())
+        .with_min_free_bytes(settings.min_free_bytes)
+        .with_dir_naming(settings.dir_naming)
+        .with_max_clone_bytes(settings.max_clone_bytes)
+        .with_max_volumes(settings.max_volumes)
+        .with_max_concurrent_clones(settings.max_concurrent_clones)
+        .with_host_policy(HostPolicy::new(
+            settings.allowed_hosts.clone(),
+            settings.denied_hosts.clone(),
+        ));
+    plugin.prune_shared().await;
+    if let Some(interval) = settings.refetch_interval {
+        plugin.spawn_refetch_task(interval);
+    }
+    if settings.object_cache {
+        plugin.spawn_object_cache_task(settings.object_cache_interval);
+    }
+    let mut app = plugin.clone().into_router();
+    // Docker speaks the plugin protocol, the admin endpoints are called by other clients.
+    if settings.strict_requests {
+        app = app.layer(axum::middleware::from_fn(
+            driver::router::require_plugin_protocol,
+        ));
+    }
+    let app = app.merge(admin::create_router(plugin));
+    let listener = Listener::bind(&settings.listen).await?;
+    if let Listen::Unix(socket) = &settings.listen {
+        set_socket_permissions(socket, settings.socket_mode, settings.socket_group)?;
+    }
+    println!("listening on {}", listener.local_addr()?);
 
-    let git = Git::init().await?;
-    let plugin = Plugin::new(&settings.mount_path, git).into_router();
-    let listener = UnixListener::bind(&settings.socket)?;
-    println!("listening on {:?}", listener.local_addr().unwrap());
+    listener
+        .serve(app, async {
+            if let Err(e) = shutdown_signal().await {
+                eprintln!("WARN: Failed to listen for shutdown signals. {}", e);
+                // Without the handlers the plugin can only be killed, but it keeps serving.
+                std::future::pending::<()>().await;
+            }
+        })
+        .await?;
 
-    serve(listener, plugin).await?;
+    if let Listen::Unix(socket) = &settings.listen {
+        fs::remove_file(socket).await?;
+    }
 
     Ok(())
 }
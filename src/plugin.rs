@@ -1,15 +1,39 @@
 use serde::Serialize;
 use std::{
+    collections::{HashMap, HashSet},
     io::ErrorKind,
+    num::NonZeroUsize,
     path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
 };
-use tokio::fs;
+use tokio::{
+    fs,
+    sync::{Mutex, Semaphore},
+    task::JoinHandle,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
 
 use crate::{
-    domains::{repo::RawRepo, volume::Status as VolumeStatus},
-    driver::{Driver, ItemVolume, VolumeInfo},
+    domains::{
+        backoff,
+        cmd::{Cmd, Error as CmdError},
+        compose, ready,
+        repo::{RawRepo, Repo},
+        stable_hash, timestamp,
+        url::HostPolicy,
+        volume::{DirNaming, Status as VolumeStatus, Volume},
+    },
+    driver::{Driver, ItemVolume, Scope, VOLUME_DRIVER, VolumeInfo},
     services::{
-        git::{Error as GitError, Git},
+        archive::{self, Error as ArchiveError},
+        disk,
+        events::{Event, Events, Kind as EventKind},
+        fsutil,
+        git::{Cloned, Error as GitError, Git},
+        metrics::Metrics,
+        store,
         volumes::{Error as VolumesError, Volumes},
     },
 };
@@ -22,23 +46,133 @@ pub enum Error {
     #[error(transparent)]
     Git(#[from] GitError),
 
+    #[error(transparent)]
+    Archive(#[from] ArchiveError),
+
     #[error("Failed deletion of directory {path} for {operation}. {kind:?}")]
     RemoveDir {
         path: PathBuf,
         operation: String,
         kind: ErrorKind,
     },
+
+    #[error("Failed to change permissions under {path}. {kind:?}")]
+    Permissions { path: PathBuf, kind: ErrorKind },
+
+    #[error("Failed to inspect {path}. {kind:?}")]
+    Inspect { path: PathBuf, kind: ErrorKind },
+
+    #[error("Refusing to remove {0}, it is not a clone made by gitvol. Remove it by hand")]
+    ForeignPath(PathBuf),
+
+    #[error("Failed to update volume {name}, its previous checkout is kept. {source}")]
+    Refetch { name: String, source: Box<Error> },
+
+    #[error("Volume {0} is not mounted")]
+    NotMounted(String),
+
+    #[error("Volume {0} was created without refetch and can not be refreshed")]
+    NotRefetchable(String),
+
+    #[error(
+        "post_clone hooks and ready_check commands are disabled, the plugin must be started with --allow-hooks"
+    )]
+    HooksNotAllowed,
+
+    #[error("post_clone hook of volume {0} failed. {1}")]
+    Hook(String, CmdError),
+
+    #[error("Clone of volume {name} takes {size} bytes, more than the limit of {limit}")]
+    CloneTooLarge { name: String, size: u64, limit: u64 },
+
+    #[error("Not enough free space to clone: {available} bytes available, {required} required")]
+    InsufficientSpace { available: u64, required: u64 },
+
+    #[error(
+        "Unknown storage_class {0:?}, the plugin must be started with --storage-class {0}=<dir>"
+    )]
+    UnknownStorageClass(String),
+
+    #[error(
+        "Credentials of {0} are not kept across restarts, create the volume again with the same options and credentials"
+    )]
+    AuthLost(String),
+
+    #[error("Directory {subpath:?} to mount is missing in the checkout of volume {name}")]
+    SubpathMissing { name: String, subpath: String },
+
+    #[error("Directory {subpath:?} to mount leads outside the checkout of volume {name}")]
+    SubpathEscapes { name: String, subpath: String },
+
+    #[error("Volume {name} is not ready: {check} did not pass within {timeout:?}")]
+    NotReady {
+        name: String,
+        check: String,
+        timeout: Duration,
+    },
+}
+
+impl Error {
+    /// Stable name of the failure for tooling, see `git::Error::code`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Volumes(e) => e.code(),
+            Self::Git(e) => e.code(),
+            Self::Archive(_) => "ARCHIVE",
+            Self::RemoveDir { .. } | Self::Permissions { .. } | Self::Inspect { .. } => "IO",
+            Self::ForeignPath(_) => "FOREIGN_PATH",
+            Self::Refetch { .. } => "REFETCH_FAILED",
+            Self::NotMounted(_) => "NOT_MOUNTED",
+            Self::NotRefetchable(_) => "NOT_REFETCHABLE",
+            Self::HooksNotAllowed => "HOOKS_NOT_ALLOWED",
+            Self::Hook(..) => "HOOK_FAILED",
+            Self::UnknownStorageClass(_) => "INVALID_OPTIONS",
+            Self::AuthLost(_) => "AUTH_LOST",
+            Self::InsufficientSpace { .. } => "DISK_FULL",
+            Self::CloneTooLarge { .. } => "CLONE_TOO_LARGE",
+            Self::SubpathMissing { .. } => "SUBPATH_MISSING",
+            Self::SubpathEscapes { .. } => "SUBPATH_ESCAPES",
+            Self::NotReady { .. } => "NOT_READY",
+        }
+    }
 }
 
 #[cfg_attr(test, derive(Debug, PartialEq, Clone))]
 #[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
 pub struct Status {
     pub status: VolumeStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    /// Branch of the checkout: the requested one, or the remote default it was cloned from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    #[serde(rename = "Size", skip_serializing_if = "Option::is_none")]
+    pub size_bytes: Option<u64>,
+    /// Whether the checkout is present on disk.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub available: Option<bool>,
+    /// Containers the volume is mounted into, a volume is only safe to remove at 0.
+    pub containers: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<String>,
 }
 
+/// Status of a volume with nothing checked out.
 impl From<VolumeStatus> for Status {
     fn from(status: VolumeStatus) -> Self {
-        Self { status }
+        Self {
+            status,
+            commit: None,
+            branch: None,
+            size_bytes: None,
+            available: Some(false),
+            containers: 0,
+            created_at: None,
+            updated_at: None,
+        }
     }
 }
 
@@ -47,6 +181,44 @@ pub struct Plugin {
     base_path: PathBuf,
     volumes: Volumes,
     git: Git,
+    refetch_debounce: Duration,
+    ready_timeout: Duration,
+    compress_cleared: bool,
+    scope: Scope,
+    implements: Vec<String>,
+    clone_permits: Arc<Semaphore>,
+    allow_hooks: bool,
+    strict_refetch: bool,
+    share_clones: bool,
+    volume_mode: Option<u32>,
+    storage_classes: HashMap<String, PathBuf>,
+    events: Events,
+    min_free_bytes: u64,
+    max_clone_bytes: Option<u64>,
+    shared: Arc<Mutex<HashMap<PathBuf, Arc<Mutex<SharedClone>>>>>,
+    metrics: Arc<Metrics>,
+}
+
+/// Clone mounted by every volume with equal options, see `Plugin::with_share_clones`.
+#[derive(Default)]
+struct SharedClone {
+    cloned: Option<Cloned>,
+    volumes: HashSet<String>,
+}
+
+/// Directory under the mount path holding the shared clones, hidden so it is not loaded
+/// as a volume.
+const SHARED_DIR: &str = ".shared";
+
+/// How long a `post_clone` hook may run before the mount fails.
+const HOOK_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Longest pause of the periodic refetch of a volume that keeps failing.
+const MAX_REFETCH_BACKOFF: Duration = Duration::from_secs(60 * 60);
+
+/// Clones allowed to run at once unless configured: one per CPU.
+pub fn default_clone_limit() -> NonZeroUsize {
+    std::thread::available_parallelism().unwrap_or(NonZeroUsize::MIN)
 }
 
 impl Plugin {
@@ -55,583 +227,3455 @@ impl Plugin {
             base_path: base_path.to_path_buf(),
             volumes: Volumes::new(),
             git,
+            refetch_debounce: Duration::ZERO,
+            ready_timeout: Duration::from_secs(60),
+            compress_cleared: false,
+            scope: Scope::Global,
+            implements: vec![VOLUME_DRIVER.to_string()],
+            clone_permits: Arc::new(Semaphore::new(default_clone_limit().get())),
+            allow_hooks: false,
+            strict_refetch: false,
+            share_clones: false,
+            volume_mode: None,
+            storage_classes: HashMap::new(),
+            events: Events::default(),
+            min_free_bytes: 0,
+            max_clone_bytes: None,
+            shared: Arc::new(Mutex::new(HashMap::new())),
+            metrics: Arc::new(Metrics::default()),
         }
     }
-}
-
-#[async_trait::async_trait]
-impl Driver for Plugin {
-    type Error = Error;
-    type Status = Status;
-    type Opts = RawRepo;
 
-    async fn path(&self, name: &str) -> Result<Option<PathBuf>, Self::Error> {
-        let Some(volume) = self.volumes.read(name).await else {
-            eprintln!("WARN: Volume named {} not found", name);
-            return Ok(None);
+    /// Like `new`, but restores the volumes saved under `base_path` and persists changes there.
+    pub async fn load(base_path: &Path, git: Git) -> Result<Self, Error> {
+        let plugin = Self {
+            volumes: Volumes::load(base_path).await?,
+            ..Self::new(base_path, git)
         };
-
-        Ok(volume.path.clone())
+        plugin.restore_markers().await;
+        plugin.restore_shared().await;
+        Ok(plugin)
     }
 
-    async fn get(&self, name: &str) -> Result<VolumeInfo<Self::Status>, Self::Error> {
-        let volume = self.volumes.try_read(name).await?;
-        Ok(VolumeInfo {
-            mountpoint: volume.path.clone(),
-            status: Status {
-                status: volume.status.clone(),
-            },
-        })
+    pub fn with_refetch_debounce(self, refetch_debounce: Duration) -> Self {
+        Self {
+            refetch_debounce,
+            ..self
+        }
     }
 
-    async fn list(&self) -> Result<Vec<ItemVolume>, Self::Error> {
-        let list = self.volumes.read_all().await;
-        Ok(list
-            .iter()
-            .map(|v| ItemVolume {
-                name: v.name.clone(),
-                mountpoint: v.path.clone(),
-            })
-            .collect())
+    pub fn with_ready_timeout(self, ready_timeout: Duration) -> Self {
+        Self {
+            ready_timeout,
+            ..self
+        }
     }
 
-    async fn create(&self, name: &str, opts: Option<Self::Opts>) -> Result<(), Self::Error> {
-        self.volumes.create(name, opts).await?;
-        Ok(())
+    pub fn with_compress_cleared(self, compress_cleared: bool) -> Self {
+        Self {
+            compress_cleared,
+            ..self
+        }
     }
 
-    async fn remove(&self, name: &str) -> Result<(), Self::Error> {
-        let Some(volume) = self.volumes.remove(name).await else {
-            eprintln!("WARN: Volume named {} not found", name);
-            return Ok(());
-        };
-
-        remove_dir_if_exists(volume.path.clone()).await?;
-
-        Ok(())
+    pub fn with_scope(self, scope: Scope) -> Self {
+        Self { scope, ..self }
     }
-    async fn mount(&self, name: &str, id: &str) -> Result<PathBuf, Self::Error> {
-        let mut volume = self.volumes.try_write(name).await?;
 
-        if let Some(path) = volume.path.clone() {
-            println!("Repository {} already cloned.", name);
-            if volume.repo.refetch {
-                println!("Attempting to refetch repository {} for id {}.", name, id);
-                self.git.refetch(&path).await?;
-            }
-            volume.containers.insert(id.to_string());
-            return Ok(path);
+    /// Caps the clones running at once, further mounts wait for a running clone to finish.
+    pub fn with_max_concurrent_clones(self, max_concurrent_clones: NonZeroUsize) -> Self {
+        Self {
+            clone_permits: Arc::new(Semaphore::new(max_concurrent_clones.get())),
+            ..self
         }
+    }
 
-        let path = volume.create_path_from(&self.base_path);
-        if path.exists() {
-            println!("Repository directory {:?} already exists. Remooving", &path);
-            fs::remove_dir_all(&path)
-                .await
-                .map_err(|e| Error::RemoveDir {
-                    path: path.clone(),
-                    operation: "exists repository dir".to_string(),
-                    kind: e.kind(),
-                })?;
+    /// Lets volumes run a `post_clone` command. Off by default: the command runs as the plugin.
+    pub fn with_allow_hooks(self, allow_hooks: bool) -> Self {
+        Self {
+            allow_hooks,
+            ..self
         }
-        self.git.clone(&path, &volume.repo).await?;
-
-        volume.containers.insert(id.to_string());
-        volume.status = VolumeStatus::Clonned;
+    }
 
-        println!("Volume {} mounted successfully.", name);
-        Ok(path)
+    /// Fails the mount when the refetch of an already cloned volume fails, instead of mounting
+    /// the previous checkout.
+    pub fn with_strict_refetch(self, strict_refetch: bool) -> Self {
+        Self {
+            strict_refetch,
+            ..self
+        }
     }
 
-    async fn unmount(&self, name: &str, id: &str) -> Result<(), Self::Error> {
-        let Some(mut volume) = self.volumes.write(name).await else {
-            eprintln!("WARN: Volume named {} not found", name);
-            return Ok(());
-        };
+    /// Volumes with equal options mount a single clone, removed once the last of them
+    /// is unmounted. Off by default: a change made through one volume shows in the others.
+    pub fn with_share_clones(self, share_clones: bool) -> Self {
+        Self {
+            share_clones,
+            ..self
+        }
+    }
 
-        volume.containers.remove(id);
+    /// Permissions applied to every checkout, so containers running as another user can
+    /// read it. Left to the umask when `None`.
+    pub fn with_volume_mode(self, volume_mode: Option<u32>) -> Self {
+        Self {
+            volume_mode,
+            ..self
+        }
+    }
 
-        if !volume.containers.is_empty() {
-            println!(
-                "Volume {} still in use by containers. container_count={}",
-                name,
-                volume.containers.len(),
-            );
-            return Ok(());
+    /// Named base directories volumes pick with `storage_class`, e.g. on faster disks.
+    pub fn with_storage_classes(self, storage_classes: HashMap<String, PathBuf>) -> Self {
+        Self {
+            storage_classes,
+            ..self
         }
+    }
 
-        volume.status = VolumeStatus::Cleared;
-        remove_dir_if_exists(volume.path.clone()).await?;
-        volume.path = None;
+    pub fn with_events(self, events: Events) -> Self {
+        Self { events, ..self }
+    }
 
-        println!("Volume {} unmounted successfully.", name);
-        Ok(())
+    /// Free bytes the filesystem of a clone must have before cloning, 0 skips the check.
+    pub fn with_min_free_bytes(self, min_free_bytes: u64) -> Self {
+        Self {
+            min_free_bytes,
+            ..self
+        }
     }
-}
 
-async fn remove_dir_if_exists(path: Option<PathBuf>) -> Result<(), Error> {
-    if let Some(path) = path
-        && path.exists()
-    {
-        println!("Attempting to remove directory {:?}", &path);
-        fs::remove_dir_all(&path)
-            .await
-            .map_err(|e| Error::RemoveDir {
-                path: path.clone(),
-                operation: "remove dir if exists".to_string(),
-                kind: e.kind(),
-            })?;
+    /// Size a checkout may take on disk, `.git` included. Larger clones fail the mount.
+    pub fn with_max_clone_bytes(self, max_clone_bytes: Option<u64>) -> Self {
+        Self {
+            max_clone_bytes,
+            ..self
+        }
     }
 
-    Ok(())
-}
+    /// Plugin types reported by `/Plugin.Activate`.
+    pub fn with_implements(self, implements: Vec<String>) -> Self {
+        Self { implements, ..self }
+    }
 
-#[cfg(test)]
-mod test_mocks {
-    use super::*;
-    use crate::services::git::test_mocks::TestRepo;
-    use std::ops::Deref;
-    use tempfile::{Builder as TempBuilder, TempDir};
+    pub fn with_host_policy(self, hosts: HostPolicy) -> Self {
+        Self {
+            volumes: self.volumes.with_host_policy(hosts),
+            ..self
+        }
+    }
 
-    pub const VOLUME_NAME: &str = "test_volume";
+    pub fn with_dir_naming(self, dir_naming: DirNaming) -> Self {
+        Self {
+            volumes: self.volumes.with_dir_naming(dir_naming),
+            ..self
+        }
+    }
 
-    pub struct TempPlugin {
-        plugin: Plugin,
-        temp: TempDir,
+    pub fn with_max_volumes(self, max_volumes: Option<usize>) -> Self {
+        Self {
+            volumes: self.volumes.with_max_volumes(max_volumes),
+            ..self
+        }
     }
 
-    impl Deref for TempPlugin {
-        type Target = Plugin;
+    /// Refetches every mounted `refetch` volume that is still used by a container.
+    /// A volume that keeps failing is retried with a growing delay based on `every`.
+    pub async fn refetch_mounted(&self, every: Duration) {
+        for listed in self.volumes.read_all().await {
+            if !listed.repo.refetch || listed.path.is_none() || listed.containers.is_empty() {
+                continue;
+            }
+            let Some(mut volume) = self.volumes.write(&listed.name).await else {
+                continue;
+            };
+            // The volume may have been unmounted while waiting for the lock.
+            if volume.path.is_none() || volume.containers.is_empty() {
+                continue;
+            }
+            if volume
+                .refetch_retry_at
+                .is_some_and(|retry_at| retry_at > Instant::now())
+            {
+                continue;
+            }
 
-        fn deref(&self) -> &Self::Target {
-            &self.plugin
+            match self.refetch_checkouts(&volume).await {
+                Ok(commit) => {
+                    info!(volume = volume.name, commit, "Periodic refetch done");
+                    volume.commit = Some(commit);
+                    volume.last_refetch = Some(Instant::now());
+                    volume.refetch_failures = 0;
+                    volume.refetch_retry_at = None;
+                    self.volumes.save(&volume).await;
+                }
+                Err(e) => {
+                    let delay =
+                        backoff::next_delay(volume.refetch_failures, every, MAX_REFETCH_BACKOFF);
+                    volume.refetch_failures += 1;
+                    volume.refetch_retry_at = Some(Instant::now() + delay);
+                    warn!(
+                        volume = volume.name,
+                        error = %e,
+                        failures = volume.refetch_failures,
+                        retry_in = ?delay,
+                        "Periodic refetch failed"
+                    );
+                }
+            }
         }
     }
 
-    impl Plugin {
-        pub async fn stub() -> Self {
-            Self::new(&std::env::temp_dir(), Git::init().await.unwrap())
+    /// Refetches a mounted volume right away, regardless of the refetch debounce.
+    pub async fn refresh(&self, name: &str) -> Result<String, Error> {
+        let mut volume = self.volumes.try_write(name).await?;
+        if volume.path.is_none() {
+            return Err(Error::NotMounted(name.to_string()));
         }
-
-        pub async fn temp() -> TempPlugin {
-            let temp = TempBuilder::new().prefix("temp-gitvol-").tempdir().unwrap();
-            let plugin = Self::new(&temp.path(), Git::init().await.unwrap());
-            TempPlugin { plugin, temp }
+        if !volume.repo.refetch && !volume.repo.bare {
+            return Err(Error::NotRefetchable(name.to_string()));
         }
 
-        pub async fn with_volume(self, volume_name: &str, raw_repo: RawRepo) -> Self {
-            self.create(volume_name, Some(raw_repo)).await.unwrap();
-            self
+        let commit = self.refetch_checkouts(&volume).await?;
+        volume.commit = Some(commit.clone());
+        volume.last_refetch = Some(Instant::now());
+        self.volumes.save(&volume).await;
+        self.events
+            .emit(Event::new(EventKind::Refreshed, name).with_commit(Some(commit.clone())));
+
+        Ok(commit)
+    }
+
+    /// Points an unmounted volume to other repository options, `branch` most of all.
+    /// The old checkout is removed, the next mount clones the new options.
+    pub async fn update(&self, name: &str, raw: RawRepo) -> Result<(), Error> {
+        self.check_hooks(raw.post_clone.as_deref(), raw.ready_check.as_deref())?;
+        self.check_storage_class(raw.storage_class.as_deref())?;
+        let (previous, mut volume) = self.volumes.update(name, raw).await?;
+        self.resolve_data_base(&mut volume)?;
+        // The directory is named after the options, the state file is saved to the new one.
+        let removed = self.remove_volume_dirs(&previous).await;
+        self.volumes.save(&volume).await;
+        removed?;
+
+        println!("Volume {} updated to {}.", name, volume.repo);
+        Ok(())
+    }
+
+    /// Refetches every checkout of a mounted volume, returns the commit they are at.
+    async fn refetch_checkouts(&self, volume: &Volume) -> Result<String, Error> {
+        if volume.tag
+            && let Some(commit) = &volume.commit
+        {
+            return Ok(commit.clone());
+        }
+        let mut commit = String::new();
+        for path in volume.checkouts(self.data_base(&volume.repo)?) {
+            commit = self.refetch(&path, &volume.repo).await?;
         }
+        Ok(commit)
+    }
 
-        pub async fn with_stub_volume(self) -> Self {
-            self.with_volume(VOLUME_NAME, RawRepo::stub()).await
+    /// Refetches a clone, lifting the read-only permissions of a `readonly` volume meanwhile.
+    async fn refetch(&self, path: &Path, repo: &Repo) -> Result<String, Error> {
+        self.volumes.check_allowed(repo)?;
+        check_auth(repo)?;
+        if !repo.readonly && self.volume_mode.is_none() {
+            return Ok(self.git.refetch(path, repo).await?);
         }
 
-        pub async fn test_is_empty_list(&self) -> &Self {
-            let list = self.list().await.unwrap();
-            assert_eq!(list.len(), 0);
-            self
+        if repo.readonly {
+            set_readonly(path, false).await?;
         }
+        let result = self.git.refetch(path, repo).await;
+        self.apply_permissions(path, repo).await?;
+        Ok(result?)
+    }
 
-        pub async fn test_in_list(&self, input_list: Vec<ItemVolume>) -> &Self {
-            let list = self.list().await.unwrap();
+    /// Applies the volume mode, then takes the write bits away from a `readonly` volume.
+    async fn apply_permissions(&self, path: &Path, repo: &Repo) -> Result<(), Error> {
+        if let Some(mode) = self.volume_mode {
+            disk::set_mode(path, mode)
+                .await
+                .map_err(|e| Error::Permissions {
+                    path: path.to_path_buf(),
+                    kind: e.kind(),
+                })?;
+        }
+        if repo.readonly {
+            set_readonly(path, true).await?;
+        }
+        Ok(())
+    }
 
-            assert_eq!(list.len(), input_list.len());
+    pub fn spawn_refetch_task(&self, every: Duration) -> JoinHandle<()> {
+        let plugin = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(every);
+            // The first tick completes immediately, volumes were just fetched on mount.
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                plugin.refetch_mounted(every).await;
+            }
+        })
+    }
 
-            for item in input_list {
-                let list_item = list.iter().find(|i| i.name == item.name);
-                assert!(
-                    list_item.is_some(),
-                    "The volume named {} was not found in the list.",
-                    item.name
-                );
+    /// Refreshes the object cache for the repositories of all volumes, the mirrors of
+    /// repositories no volume uses anymore are removed.
+    pub async fn refresh_object_cache(&self) {
+        let repos: Vec<Repo> = self
+            .volumes
+            .read_all()
+            .await
+            .into_iter()
+            .map(|volume| volume.repo)
+            .collect();
+        self.git.refresh_object_cache(&repos).await;
+    }
 
-                let mountpoint = list_item.and_then(|i| i.mountpoint.clone());
-                assert_eq!(item.mountpoint, mountpoint);
+    pub fn spawn_object_cache_task(&self, every: Duration) -> JoinHandle<()> {
+        let plugin = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(every);
+            // The first tick completes immediately, the mirrors of volumes removed while the
+            // plugin was down go at startup.
+            loop {
+                interval.tick().await;
+                plugin.refresh_object_cache().await;
             }
+        })
+    }
 
-            self
+    /// Mounts a volume that already has a clone, refetching it when due.
+    /// Returns `None` when the volume still has to be cloned.
+    async fn mount_cloned(&self, name: &str, id: &str) -> Result<Option<PathBuf>, Error> {
+        let mut volume = self.volumes.try_write(name).await?;
+        let Some(path) = volume.path.clone() else {
+            return Ok(None);
+        };
+        if volume.repo.isolated {
+            return Ok(None);
         }
-
-        pub async fn test_in_list_by_names(&self, input_list: Vec<&str>) -> &Self {
-            self.test_in_list(
-                input_list
-                    .into_iter()
-                    .map(|name| ItemVolume {
-                        name: name.to_string(),
-                        mountpoint: None,
-                    })
-                    .collect(),
-            )
-            .await
+        if !path.exists() {
+            eprintln!(
+                "WARN: Directory {:?} of volume {} was removed, cloning again.",
+                path, name
+            );
+            volume.path = None;
+            volume.commit = None;
+            volume.default_branch = None;
+            volume.status = VolumeStatus::Created;
+            self.volumes.save(&volume).await;
+            return Ok(None);
         }
 
-        pub async fn test_path_is(&self, volume_name: &str, path: Option<PathBuf>) -> &Self {
-            let path_result = self.path(volume_name).await.unwrap();
-            assert_eq!(path_result, path);
-            self
+        println!("Repository {} already cloned.", name);
+        if volume.repo.refetch {
+            if volume.tag {
+                println!(
+                    "Skipping refetch of repository {} for id {}. Tags do not move.",
+                    name, id
+                );
+            } else if volume.is_refetch_due(self.refetch_debounce) {
+                println!("Attempting to refetch repository {} for id {}.", name, id);
+                match self.refetch(&path, &volume.repo).await {
+                    Ok(commit) => {
+                        volume.commit = Some(commit);
+                        volume.last_refetch = Some(Instant::now());
+                    }
+                    Err(e) if self.strict_refetch => {
+                        return Err(Error::Refetch {
+                            name: name.to_string(),
+                            source: Box::new(e),
+                        });
+                    }
+                    Err(e) => eprintln!(
+                        "WARN: Failed to refetch repository {}, mounting the previous checkout. {}",
+                        name, e
+                    ),
+                }
+            } else {
+                println!(
+                    "Skipping refetch of repository {} for id {}. Refetched recently.",
+                    name, id
+                );
+            }
         }
+        let mountpoint = check_mountpoint(name, &path, &volume.repo)?;
+        volume.containers.insert(id.to_string());
+        volume.updated_at = SystemTime::now();
+        self.volumes.save(&volume).await;
 
-        pub async fn test_stub_path_is(&self, path: Option<PathBuf>) -> &Self {
-            self.test_path_is(VOLUME_NAME, path).await
-        }
+        Ok(Some(mountpoint))
+    }
 
-        pub async fn test_get_volume(&self, volume_name: &str, info: VolumeInfo<Status>) -> &Self {
-            let volume = self.get(volume_name).await.unwrap();
-            assert_eq!(volume, info);
-            self
-        }
+    fn shared_path(&self, repo: &Repo) -> Result<PathBuf, Error> {
+        Ok(self
+            .data_base(repo)?
+            .join(SHARED_DIR)
+            .join(stable_hash::hex(repo)))
+    }
 
-        pub async fn test_get_stub_volume(&self, info: VolumeInfo<Status>) -> &Self {
-            self.test_get_volume(VOLUME_NAME, info).await
-        }
+    fn is_shared(&self, path: &Path) -> bool {
+        std::iter::once(&self.base_path)
+            .chain(self.storage_classes.values())
+            .any(|base| path.starts_with(base.join(SHARED_DIR)))
     }
 
-    impl TempPlugin {
-        pub async fn with_temp_volume(self, volume_name: &str, raw_repo: RawRepo) -> Self {
-            let plugin = self.plugin.with_volume(volume_name, raw_repo).await;
+    async fn shared_clone(&self, path: &Path) -> Arc<Mutex<SharedClone>> {
+        let mut shared = self.shared.lock().await;
+        shared.entry(path.to_path_buf()).or_default().clone()
+    }
 
-            Self {
-                plugin,
-                temp: self.temp,
+    /// Marks the existing directories of loaded volumes, those saved before markers were
+    /// written would be taken for foreign ones otherwise. Their state tells they are gitvol's.
+    async fn restore_markers(&self) {
+        for volume in self.volumes.read_all().await {
+            let exists = self
+                .data_base(&volume.repo)
+                .is_ok_and(|base| volume.dir(base).exists());
+            if exists {
+                _ = self.mark_volume(&volume).await;
             }
         }
+    }
 
-        pub async fn with_stub_test_repo(self) -> (TestRepo, Self) {
-            let test_repo = TestRepo::new();
-            let plugin = self
-                .with_temp_volume(VOLUME_NAME, test_repo.create_raw_repo(None, None, None))
-                .await;
-            (test_repo, plugin)
+    /// Counts the loaded volumes still mounting a shared clone as its users.
+    async fn restore_shared(&self) {
+        for volume in self.volumes.read_all().await {
+            let Some(path) = volume.path.filter(|path| self.is_shared(path)) else {
+                continue;
+            };
+            let shared = self.shared_clone(&path).await;
+            let mut shared = shared.lock().await;
+            shared.volumes.insert(volume.name);
+            shared.cloned = Some(Cloned {
+                commit: volume.commit.unwrap_or_default(),
+                default_branch: volume.default_branch,
+                tag: volume.tag,
+            });
         }
     }
 
-    impl TestRepo {
-        pub fn create_raw_repo(
-            &self,
-            branch: Option<String>,
-            tag: Option<String>,
-            refetch: Option<String>,
-        ) -> RawRepo {
-            RawRepo {
-                url: Some(self.path().as_os_str().to_str().unwrap().to_string()),
-                branch,
-                tag,
-                refetch,
+    /// Removes the shared clones no volume uses anymore, e.g. left by a crash between the
+    /// removal of their last volume and their own. Runs once the storage classes are set.
+    pub async fn prune_shared(&self) {
+        let used: HashSet<PathBuf> = {
+            let shared = self.shared.lock().await;
+            let mut used = HashSet::new();
+            for (path, clone) in shared.iter() {
+                if !clone.lock().await.volumes.is_empty() {
+                    used.insert(path.clone());
+                }
+            }
+            used
+        };
+        let bases = std::iter::once(&self.base_path).chain(self.storage_classes.values());
+        for base in bases {
+            let Ok(mut entries) = fs::read_dir(base.join(SHARED_DIR)).await else {
+                continue;
+            };
+            while let Ok(Some(entry)) = entries.next_entry().await {
+                let path = entry.path();
+                if used.contains(&path) {
+                    continue;
+                }
+                if let Err(e) = remove_dir_if_exists(Some(path.clone())).await {
+                    eprintln!("WARN: Failed to prune shared clone {:?}: {:?}", path, e);
+                }
+            }
+        }
+    }
+
+    /// Mounts the clone of every volume with the same options, cloning it for the first one.
+    async fn mount_shared(
+        &self,
+        name: &str,
+        id: &str,
+        repo: &Repo,
+        cancel: &CancellationToken,
+    ) -> Result<PathBuf, Error> {
+        let path = self.shared_path(repo)?;
+        let shared = self.shared_clone(&path).await;
+        let mut shared = shared.lock().await;
+
+        let cloned = match &shared.cloned {
+            Some(cloned) if path.exists() => {
+                println!("Volume {} shares the clone at {:?}.", name, path);
+                cloned.clone()
+            }
+            _ => {
+                // Nothing but gitvol writes under the shared directory.
+                remove_stale_clone(&path, None).await?;
+                self.volumes.try_write(name).await?.status = VolumeStatus::Cloning;
+                let cloned = match self.clone_into(name, &path, repo, cancel).await {
+                    Ok(cloned) => cloned,
+                    Err(e) => {
+                        if let Some(mut volume) = self.volumes.write(name).await {
+                            volume.status = VolumeStatus::Failed {
+                                reason: e.to_string(),
+                            };
+                            self.volumes.save(&volume).await;
+                        }
+                        return Err(e);
+                    }
+                };
+                shared.cloned = Some(cloned.clone());
+                cloned
+            }
+        };
+
+        let mut volume = match self.volumes.try_write(name).await {
+            Ok(volume) => volume,
+            Err(e) => {
+                eprintln!("WARN: Volume {} was removed while cloning.", name);
+                if shared.volumes.is_empty() {
+                    shared.cloned = None;
+                    remove_partial_clone(&path).await?;
+                }
+                return Err(e.into());
+            }
+        };
+        shared.volumes.insert(name.to_string());
+        volume.path = Some(path.clone());
+        volume.containers.insert(id.to_string());
+        volume.updated_at = SystemTime::now();
+        volume.status = VolumeStatus::Clonned;
+        volume.commit = Some(cloned.commit);
+        volume.default_branch = cloned.default_branch;
+        volume.tag = cloned.tag;
+        volume.last_refetch = Some(Instant::now());
+        self.volumes.save(&volume).await;
+
+        println!("Volume {} mounted successfully.", name);
+        Ok(repo.mountpoint(&path))
+    }
+
+    /// Drops the volume from the users of a shared clone, the last one removes it.
+    async fn release_shared(&self, name: &str, path: &Path) -> Result<(), Error> {
+        let shared = self.shared_clone(path).await;
+        let mut shared = shared.lock().await;
+        shared.volumes.remove(name);
+        if !shared.volumes.is_empty() {
+            println!(
+                "Clone {:?} still used by other volumes. volume_count={}",
+                path,
+                shared.volumes.len()
+            );
+            return Ok(());
+        }
+
+        shared.cloned = None;
+        remove_dir_if_exists(Some(path.to_path_buf())).await
+    }
+
+    async fn mount_volume(
+        &self,
+        name: &str,
+        id: &str,
+        cancel: &CancellationToken,
+    ) -> Result<PathBuf, Error> {
+        if self.volumes.try_read(name).await?.repo.isolated {
+            return self.mount_isolated(name, id, cancel).await;
+        }
+        if let Some(path) = self.mount_cloned(name, id).await? {
+            return Ok(path);
+        }
+
+        // Only one mounter clones, concurrent ones wait here and reuse its clone. The volume
+        // itself is not locked meanwhile, so get/list/path keep answering during a long clone.
+        let gate = self.volumes.clone_gate(name).await;
+        let _cloning = gate.lock().await;
+        if let Some(path) = self.mount_cloned(name, id).await? {
+            return Ok(path);
+        }
+
+        let (repo, path, dir, archive) = {
+            let volume = self.volumes.try_read(name).await?;
+            let base = self.data_base(&volume.repo)?;
+            let (path, dir) = (volume.data_path(base), volume.dir(base));
+            // Checked before marking, a marker only vouches for what gitvol created.
+            remove_stale_clone(&path, Some(&dir)).await?;
+            self.mark_volume(&volume).await?;
+            (volume.repo.clone(), path, dir, volume.archive.clone())
+        };
+
+        if let Some(archive) = archive {
+            let expanded = archive::expand(&archive, &path).await;
+            let mut volume = self.volumes.try_write(name).await?;
+            volume.archive = None;
+            match expanded {
+                Ok(()) => {
+                    self.apply_permissions(&path, &repo).await?;
+                    volume.path = Some(path.clone());
+                    volume.status = VolumeStatus::Clonned;
+                    drop(volume);
+                    if let Some(path) = self.mount_cloned(name, id).await? {
+                        return Ok(path);
+                    }
+                }
+                Err(e) => {
+                    eprintln!(
+                        "WARN: Failed to expand archive of volume {}, cloning again. {}",
+                        name, e
+                    );
+                    self.volumes.save(&volume).await;
+                }
+            }
+        }
+
+        if self.share_clones {
+            return self.mount_shared(name, id, &repo, cancel).await;
+        }
+
+        remove_stale_clone(&path, Some(&dir)).await?;
+        self.volumes.try_write(name).await?.status = VolumeStatus::Cloning;
+        let cloned = match self.clone_into(name, &path, &repo, cancel).await {
+            Ok(cloned) => cloned,
+            Err(e) => {
+                if let Some(mut volume) = self.volumes.write(name).await {
+                    volume.status = VolumeStatus::Failed {
+                        reason: e.to_string(),
+                    };
+                    self.volumes.save(&volume).await;
+                }
+                return Err(e);
+            }
+        };
+
+        let mut volume = match self.volumes.try_write(name).await {
+            Ok(volume) => volume,
+            Err(e) => {
+                eprintln!("WARN: Volume {} was removed while cloning.", name);
+                remove_partial_clone(&path).await?;
+                return Err(e.into());
+            }
+        };
+        volume.path = Some(path.clone());
+        volume.containers.insert(id.to_string());
+        volume.updated_at = SystemTime::now();
+        volume.status = VolumeStatus::Clonned;
+        volume.commit = Some(cloned.commit);
+        volume.default_branch = cloned.default_branch;
+        volume.tag = cloned.tag;
+        volume.last_refetch = Some(Instant::now());
+        self.volumes.save(&volume).await;
+
+        println!("Volume {} mounted successfully.", name);
+        Ok(repo.mountpoint(&path))
+    }
+
+    /// Gives the container a checkout of its own, other containers never see its changes.
+    async fn mount_isolated(
+        &self,
+        name: &str,
+        id: &str,
+        cancel: &CancellationToken,
+    ) -> Result<PathBuf, Error> {
+        let (repo, path) = {
+            let volume = self.volumes.try_read(name).await?;
+            let base = self.data_base(&volume.repo)?;
+            let path = volume.container_path(base, id);
+            if volume.containers.contains(id) && path.exists() {
+                println!("Volume {} already mounted for id {}.", name, id);
+                return Ok(volume.repo.mountpoint(&path));
+            }
+            remove_stale_clone(&path, Some(&volume.dir(base))).await?;
+            self.mark_volume(&volume).await?;
+            (volume.repo.clone(), path)
+        };
+
+        {
+            let mut volume = self.volumes.try_write(name).await?;
+            if volume.containers.is_empty() {
+                volume.status = VolumeStatus::Cloning;
+            }
+        }
+        let cloned = match self.clone_into(name, &path, &repo, cancel).await {
+            Ok(cloned) => cloned,
+            Err(e) => {
+                if let Some(mut volume) = self.volumes.write(name).await
+                    && volume.containers.is_empty()
+                {
+                    volume.status = VolumeStatus::Failed {
+                        reason: e.to_string(),
+                    };
+                    self.volumes.save(&volume).await;
+                }
+                return Err(e);
+            }
+        };
+
+        let mut volume = match self.volumes.try_write(name).await {
+            Ok(volume) => volume,
+            Err(e) => {
+                eprintln!("WARN: Volume {} was removed while cloning.", name);
+                remove_partial_clone(&path).await?;
+                return Err(e.into());
+            }
+        };
+        volume.path = Some(volume.checkouts_path(self.data_base(&volume.repo)?));
+        volume.containers.insert(id.to_string());
+        volume.updated_at = SystemTime::now();
+        volume.status = VolumeStatus::Clonned;
+        volume.commit = Some(cloned.commit);
+        volume.default_branch = cloned.default_branch;
+        volume.tag = cloned.tag;
+        volume.last_refetch = Some(Instant::now());
+        self.volumes.save(&volume).await;
+
+        println!("Volume {} mounted for id {} at {:?}.", name, id, path);
+        Ok(repo.mountpoint(&path))
+    }
+
+    /// Clones into `path` and prepares the checkout for mounting.
+    /// Whatever was cloned is removed again on failure.
+    async fn clone_into(
+        &self,
+        name: &str,
+        path: &Path,
+        repo: &Repo,
+        cancel: &CancellationToken,
+    ) -> Result<Cloned, Error> {
+        self.volumes.check_allowed(repo)?;
+        check_auth(repo)?;
+        self.check_free_space(path).await?;
+        let cloned = {
+            let _permit = tokio::select! {
+                permit = self.clone_permits.acquire() => permit,
+                _ = cancel.cancelled() => return Err(GitError::Cancelled(repo.to_string()).into()),
+            };
+            let started = Instant::now();
+            let cloned = self.git.clone(path, repo, cancel).await;
+            self.metrics
+                .observe_clone(cloned.is_ok(), started.elapsed());
+            cloned
+        };
+        let cloned = match cloned {
+            Ok(cloned) => cloned,
+            Err(e) => {
+                remove_partial_clone(path).await?;
+                return Err(e.into());
+            }
+        };
+
+        if let Err(e) = self.check_clone_size(name, path).await {
+            remove_partial_clone(path).await?;
+            return Err(e);
+        }
+
+        if let Some(command) = &repo.post_clone
+            && let Err(e) = self.run_hook(name, command, path, cancel).await
+        {
+            remove_partial_clone(path).await?;
+            return Err(e);
+        }
+
+        if let Some(check) = &repo.ready_check {
+            // Checked again like the hook, the plugin may have been restarted without hooks.
+            if let Err(e) = self.check_hooks(None, Some(&check.to_option())) {
+                remove_partial_clone(path).await?;
+                return Err(e);
+            }
+            println!("Waiting for volume {} to be ready. {}", name, check);
+            if !check.wait(path, self.ready_timeout, cancel).await {
+                remove_partial_clone(path).await?;
+                if cancel.is_cancelled() {
+                    return Err(GitError::Cancelled(repo.to_string()).into());
+                }
+                return Err(Error::NotReady {
+                    name: name.to_string(),
+                    check: check.to_string(),
+                    timeout: self.ready_timeout,
+                });
+            }
+        }
+
+        if let Err(e) = check_mountpoint(name, path, repo) {
+            remove_partial_clone(path).await?;
+            return Err(e);
+        }
+
+        if repo.readonly {
+            println!("Making volume {} read-only.", name);
+        }
+        if let Err(e) = self.apply_permissions(path, repo).await {
+            remove_partial_clone(path).await?;
+            return Err(e);
+        }
+
+        Ok(cloned)
+    }
+
+    fn check_hooks(
+        &self,
+        post_clone: Option<&str>,
+        ready_check: Option<&str>,
+    ) -> Result<(), Error> {
+        let runs_command = post_clone.is_some() || ready_check.is_some_and(ready::is_command);
+        if runs_command && !self.allow_hooks {
+            return Err(Error::HooksNotAllowed);
+        }
+        Ok(())
+    }
+
+    /// Fails a clone larger than `max_clone_bytes`, before its hooks run on it.
+    async fn check_clone_size(&self, name: &str, path: &Path) -> Result<(), Error> {
+        let Some(limit) = self.max_clone_bytes else {
+            return Ok(());
+        };
+        let size = match disk::dir_size(path).await {
+            Ok(size) => size,
+            Err(e) => {
+                eprintln!("WARN: Failed to measure clone {:?}. {}", path, e);
+                return Ok(());
+            }
+        };
+        if size > limit {
+            return Err(Error::CloneTooLarge {
+                name: name.to_string(),
+                size,
+                limit,
+            });
+        }
+        Ok(())
+    }
+
+    /// Marks the volume directories a clone goes to, see `store::mark`. Failing to is only
+    /// logged, the marker is informational.
+    async fn mark_volume(&self, volume: &Volume) -> Result<(), Error> {
+        let data_base = self.data_base(&volume.repo)?;
+        let class_base = (data_base != self.base_path).then_some(data_base);
+        for base in std::iter::once(self.base_path.as_path()).chain(class_base) {
+            if let Err(e) = store::mark(base, volume).await {
+                eprintln!(
+                    "WARN: Failed to mark directory of volume {}. {}",
+                    volume.name, e
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails early instead of leaving a clone behind that filled the disk.
+    async fn check_free_space(&self, path: &Path) -> Result<(), Error> {
+        if self.min_free_bytes == 0 {
+            return Ok(());
+        }
+        // The clone and possibly the volume directory do not exist yet.
+        let Some(existing) = path.ancestors().find(|dir| dir.exists()) else {
+            return Ok(());
+        };
+        let available = match disk::available_space(existing).await {
+            Ok(available) => available,
+            Err(e) => {
+                eprintln!("WARN: Failed to check free space of {:?}. {}", existing, e);
+                return Ok(());
+            }
+        };
+        if available < self.min_free_bytes {
+            return Err(Error::InsufficientSpace {
+                available,
+                required: self.min_free_bytes,
+            });
+        }
+        Ok(())
+    }
+
+    fn check_storage_class(&self, storage_class: Option<&str>) -> Result<(), Error> {
+        match storage_class
+            .map(str::trim)
+            .filter(|class| !class.is_empty())
+        {
+            Some(class) if !self.storage_classes.contains_key(class) => {
+                Err(Error::UnknownStorageClass(class.to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Base directory of the clones of a volume: the one of its `storage_class` or the
+    /// mount path. The state file always stays under the mount path.
+    fn data_base(&self, repo: &Repo) -> Result<&Path, Error> {
+        match &repo.storage_class {
+            Some(class) => self
+                .storage_classes
+                .get(class)
+                .map(PathBuf::as_path)
+                .ok_or_else(|| Error::UnknownStorageClass(class.clone())),
+            None => Ok(&self.base_path),
+        }
+    }
+
+    /// Records the base directory the storage class of the volume resolves to.
+    fn resolve_data_base(&self, volume: &mut Volume) -> Result<(), Error> {
+        volume.data_base = match volume.repo.storage_class {
+            Some(_) => Some(self.data_base(&volume.repo)?.to_path_buf()),
+            None => None,
+        };
+        Ok(())
+    }
+
+    /// Removes the volume directory, and the one holding its clones under a storage class.
+    async fn remove_volume_dirs(&self, volume: &Volume) -> Result<(), Error> {
+        remove_dir_if_exists(Some(volume.dir(&self.base_path))).await?;
+        let base = match (&volume.data_base, self.data_base(&volume.repo)) {
+            (Some(base), _) => base.as_path(),
+            (None, Ok(base)) => base,
+            (None, Err(e)) => {
+                eprintln!(
+                    "WARN: Clone of volume {} is left on disk, its base is unknown. {}",
+                    volume.name, e
+                );
+                return Ok(());
+            }
+        };
+        if base != self.base_path {
+            remove_dir_if_exists(Some(volume.dir(base))).await?;
+        }
+        Ok(())
+    }
+
+    async fn run_hook(
+        &self,
+        name: &str,
+        command: &str,
+        path: &Path,
+        cancel: &CancellationToken,
+    ) -> Result<(), Error> {
+        // Checked again for volumes created before the plugin was restarted without hooks.
+        self.check_hooks(Some(command), None)?;
+        println!("Running post_clone hook of volume {}. {:?}", name, command);
+        let output = Cmd::new("sh")
+            .arg("-c")
+            .arg(command)
+            .current_dir(path)
+            .timeout(Some(HOOK_TIMEOUT))
+            .cancel_on(cancel)
+            .exec_streaming()
+            .await
+            .map_err(|e| Error::Hook(name.to_string(), e))?;
+        println!("post_clone hook output: {}", output);
+        Ok(())
+    }
+
+    /// Checks the options of a volume without creating it. With `check_remote` the remote is
+    /// also asked for the requested ref, which needs network access.
+    pub async fn validate(&self, raw: RawRepo, check_remote: bool) -> Result<Repo, Error> {
+        let repo = self.volumes.validate(raw)?;
+        self.check_hooks(
+            repo.post_clone.as_deref(),
+            repo.ready_check.as_ref().map(|c| c.to_option()).as_deref(),
+        )?;
+        self.check_storage_class(repo.storage_class.as_deref())?;
+        if check_remote {
+            self.git.check_remote(&repo).await?;
+        }
+        Ok(repo)
+    }
+
+    /// Removes the cleared, unused volumes together with their directories.
+    pub async fn prune(&self) -> Result<usize, Error> {
+        let pruned = self.volumes.prune().await;
+        for volume in &pruned {
+            println!("Pruning volume {}.", volume.name);
+            self.remove_volume_dirs(volume).await?;
+        }
+        Ok(pruned.len())
+    }
+
+    /// Everything known about a volume, with its credentials masked.
+    pub async fn inspect(&self, name: &str) -> Result<Volume, Error> {
+        let volume = self.volumes.try_read(name).await?;
+        Ok(Volume {
+            repo: volume.repo.masked(),
+            ..volume.clone()
+        })
+    }
+
+    /// Volumes whose status is named `status` (see `VolumeStatus::name`), all when `None`.
+    pub async fn list_by_status(&self, status: Option<&str>) -> Vec<ItemVolume> {
+        self.volumes
+            .filter_map(|v| {
+                status
+                    .is_none_or(|status| v.status.name() == status)
+                    .then(|| ItemVolume {
+                        name: v.name.clone(),
+                        mountpoint: v.mountpoint(),
+                    })
+            })
+            .await
+    }
+
+    pub async fn metrics(&self) -> String {
+        let active = self
+            .volumes
+            .filter_map(|v| (!v.containers.is_empty()).then_some(()))
+            .await
+            .len();
+        self.metrics.render(active)
+    }
+
+    pub fn git_version(&self) -> &str {
+        self.git.version()
+    }
+
+    pub fn git_path(&self) -> &str {
+        self.git.git_path()
+    }
+
+    pub async fn check_git(&self) -> Result<String, Error> {
+        Ok(self.git.check().await?)
+    }
+
+    pub async fn volume_count(&self) -> usize {
+        self.volumes.count().await
+    }
+
+    pub async fn export(&self) -> String {
+        let list = self.volumes.read_all().await;
+        compose::to_yaml(&list)
+    }
+}
+
+#[async_trait::async_trait]
+impl Driver for Plugin {
+    type Error = Error;
+    type Status = Status;
+    type Opts = RawRepo;
+
+    async fn activate(&self) -> Result<Vec<String>, Self::Error> {
+        Ok(self.implements.clone())
+    }
+
+    async fn capabilities(&self) -> Result<Scope, Self::Error> {
+        Ok(self.scope)
+    }
+
+    async fn path(&self, name: &str) -> Result<Option<PathBuf>, Self::Error> {
+        let Some(volume) = self.volumes.read(name).await else {
+            eprintln!("WARN: Volume named {} not found", name);
+            return Ok(None);
+        };
+
+        Ok(volume.mountpoint())
+    }
+
+    async fn get(&self, name: &str) -> Result<Option<VolumeInfo<Self::Status>>, Self::Error> {
+        let Some(volume) = self.volumes.read(name).await else {
+            debug!(volume = name, "Volume not found");
+            return Ok(None);
+        };
+        // A checkout that is gone is only unavailable, one that can not be inspected is broken.
+        let available = match &volume.path {
+            Some(path) => fs::try_exists(path).await.map_err(|e| Error::Inspect {
+                path: path.clone(),
+                kind: e.kind(),
+            })?,
+            None => false,
+        };
+        let size_bytes = match &volume.path {
+            Some(path) => match disk::dir_size(path).await {
+                Ok(size) => Some(size),
+                Err(e) => {
+                    warn!(volume = name, error = %e, "Failed to compute volume size");
+                    None
+                }
+            },
+            None => None,
+        };
+        Ok(Some(VolumeInfo {
+            mountpoint: volume.mountpoint(),
+            status: Status {
+                status: volume.status.clone(),
+                commit: volume.commit.clone(),
+                branch: volume.commit.as_ref().and_then(|_| {
+                    volume
+                        .repo
+                        .branch
+                        .clone()
+                        .or_else(|| volume.default_branch.clone())
+                }),
+                size_bytes,
+                available: Some(available),
+                containers: volume.containers.len(),
+                created_at: Some(timestamp::rfc3339(volume.created_at)),
+                updated_at: Some(timestamp::rfc3339(volume.updated_at)),
+            },
+        }))
+    }
+
+    async fn list(&self) -> Result<Vec<ItemVolume>, Self::Error> {
+        Ok(self.list_by_status(None).await)
+    }
+
+    async fn create(&self, name: &str, opts: Option<Self::Opts>) -> Result<(), Self::Error> {
+        self.check_hooks(
+            opts.as_ref().and_then(|o| o.post_clone.as_deref()),
+            opts.as_ref().and_then(|o| o.ready_check.as_deref()),
+        )?;
+        self.check_storage_class(opts.as_ref().and_then(|o| o.storage_class.as_deref()))?;
+        let (mut volume, created) = self.volumes.create(name, opts).await?;
+        if !created {
+            println!("Volume {} already exists with the same options.", name);
+            return Ok(());
+        }
+        if volume.repo.storage_class.is_some() {
+            self.resolve_data_base(&mut volume)?;
+            self.volumes.save(&volume).await;
+        }
+        drop(volume);
+        self.events.emit(Event::new(EventKind::Created, name));
+        Ok(())
+    }
+
+    async fn remove(&self, name: &str) -> Result<(), Self::Error> {
+        let Some(volume) = self.volumes.remove(name).await? else {
+            eprintln!("WARN: Volume named {} not found", name);
+            return Ok(());
+        };
+
+        // The clone, its archive and the state file all live in the volume directories.
+        self.remove_volume_dirs(&volume).await?;
+        self.events.emit(Event::new(EventKind::Removed, name));
+
+        Ok(())
+    }
+    async fn mount(&self, name: &str, id: &str) -> Result<PathBuf, Self::Error> {
+        self.mount_cancellable(name, id, CancellationToken::new())
+            .await
+    }
+
+    async fn mount_cancellable(
+        &self,
+        name: &str,
+        id: &str,
+        cancel: CancellationToken,
+    ) -> Result<PathBuf, Self::Error> {
+        let mounted = self.mount_volume(name, id, &cancel).await;
+        let event = match &mounted {
+            Ok(_) => {
+                let commit = self.volumes.read(name).await.and_then(|v| v.commit.clone());
+                Event::new(EventKind::Mounted, name).with_commit(commit)
+            }
+            Err(e) => Event::new(EventKind::MountFailed, name).with_error(e),
+        };
+        self.events.emit(event.with_id(id));
+        mounted
+    }
+
+    async fn unmount(&self, name: &str, id: &str) -> Result<(), Self::Error> {
+        let Some(mut volume) = self.volumes.write(name).await else {
+            eprintln!("WARN: Volume named {} not found", name);
+            return Ok(());
+        };
+
+        volume.containers.remove(id);
+        volume.updated_at = SystemTime::now();
+        if volume.repo.isolated
+            && let Ok(base) = self.data_base(&volume.repo)
+        {
+            remove_dir_if_exists(Some(volume.container_path(base, id))).await?;
+        }
+
+        if !volume.containers.is_empty() {
+            println!(
+                "Volume {} still in use by containers. container_count={}",
+                name,
+                volume.containers.len(),
+            );
+            self.volumes.save(&volume).await;
+            self.events
+                .emit(Event::new(EventKind::Unmounted, name).with_id(id));
+            return Ok(());
+        }
+
+        volume.status = VolumeStatus::Cleared;
+        match volume.path.clone() {
+            Some(path) if self.is_shared(&path) => {
+                self.release_shared(name, &path).await?;
+                volume.commit = None;
+                volume.default_branch = None;
+            }
+            Some(path) if self.compress_cleared && !volume.repo.isolated && path.exists() => {
+                // Keeps the archive removable, permissions are applied again on expand.
+                set_readonly(&path, false).await?;
+                volume.archive = Some(archive::compress(&path).await?);
+            }
+            path => {
+                remove_dir_if_exists(path).await?;
+                volume.commit = None;
+                volume.default_branch = None;
             }
         }
+        volume.path = None;
+        self.volumes.save(&volume).await;
+        self.events
+            .emit(Event::new(EventKind::Unmounted, name).with_id(id));
+
+        println!("Volume {} unmounted successfully.", name);
+        Ok(())
+    }
+}
+
+/// Mountpoint of the checkout at `path`, its `mount_subpath` has to be a directory inside
+/// the checkout. The subpath is resolved, a committed symlink must not lead docker to a
+/// host directory.
+fn check_mountpoint(name: &str, path: &Path, repo: &Repo) -> Result<PathBuf, Error> {
+    let mountpoint = repo.mountpoint(path);
+    let Some(subpath) = &repo.mount_subpath else {
+        return Ok(mountpoint);
+    };
+    if !mountpoint.is_dir() {
+        return Err(Error::SubpathMissing {
+            name: name.to_string(),
+            subpath: subpath.clone(),
+        });
+    }
+    match (mountpoint.canonicalize(), path.canonicalize()) {
+        (Ok(resolved), Ok(root)) if resolved.starts_with(&root) => Ok(mountpoint),
+        _ => Err(Error::SubpathEscapes {
+            name: name.to_string(),
+            subpath: subpath.clone(),
+        }),
+    }
+}
+
+async fn set_readonly(path: &Path, readonly: bool) -> Result<(), Error> {
+    disk::set_readonly(path, readonly)
+        .await
+        .map_err(|e| Error::Permissions {
+            path: path.to_path_buf(),
+            kind: e.kind(),
+        })
+}
+
+/// Removes what an interrupted mount left at `path`. It is only removed when the volume
+/// directory `volume_dir` holding it carries the `store::MARKER_FILE`, a directory gitvol
+/// did not create there is reported instead. Without `volume_dir` the path is gitvol's own.
+async fn remove_stale_clone(path: &Path, volume_dir: Option<&Path>) -> Result<(), Error> {
+    if fs::symlink_metadata(path).await.is_err() {
+        return Ok(());
+    }
+    if volume_dir.is_some_and(|dir| !dir.join(store::MARKER_FILE).exists()) {
+        return Err(Error::ForeignPath(path.to_path_buf()));
+    }
+    println!("Repository directory {:?} already exists. Removing", path);
+    remove_dir_if_exists(Some(path.to_path_buf())).await
+}
+
+/// Removes a clone that did not make it into the volume, so the next mount starts clean.
+/// The volume directory goes too unless it still holds the volume state.
+async fn remove_partial_clone(path: &Path) -> Result<(), Error> {
+    if path.exists() {
+        println!("Attempting to remove partial clone {:?}", path);
+        set_readonly(path, false).await?;
+        fs::remove_dir_all(path)
+            .await
+            .map_err(|e| Error::RemoveDir {
+                path: path.to_path_buf(),
+                operation: "cleanup after failed clone".to_string(),
+                kind: e.kind(),
+            })?;
+    }
+    if let Some(dir) = path.parent() {
+        // The marker alone does not keep a volume directory without state.
+        if !dir.join(store::REPO_FILE).exists() {
+            _ = fs::remove_file(dir.join(store::MARKER_FILE)).await;
+        }
+        // Fails when the directory is not empty, which is expected.
+        _ = fs::remove_dir(dir).await;
+    }
+
+    Ok(())
+}
+
+/// Fails instead of reaching the remote with the placeholder a restart left for the secret.
+fn check_auth(repo: &Repo) -> Result<(), Error> {
+    if repo.lost_auth() {
+        return Err(Error::AuthLost(repo.to_string()));
+    }
+    Ok(())
+}
+
+async fn remove_dir_if_exists(path: Option<PathBuf>) -> Result<(), Error> {
+    let Some(path) = path else {
+        return Ok(());
+    };
+    if path.exists() {
+        println!("Attempting to remove directory {:?}", &path);
+        // A read-only volume can not be removed as is by a non-root plugin.
+        set_readonly(&path, false).await?;
+        fsutil::remove_all(&path)
+            .await
+            .map_err(|e| Error::RemoveDir {
+                path: path.clone(),
+                operation: "remove dir if exists".to_string(),
+                kind: e.kind(),
+            })?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test_mocks {
+    use super::*;
+    use crate::services::git::test_mocks::TestRepo;
+    use std::ops::Deref;
+    use tempfile::{Builder as TempBuilder, TempDir};
+
+    pub const VOLUME_NAME: &str = "test_volume";
+
+    pub struct TempPlugin {
+        plugin: Plugin,
+        temp: TempDir,
+    }
+
+    impl Status {
+        pub fn without_times(self) -> Self {
+            assert!(self.created_at.is_some() && self.updated_at.is_some());
+            Self {
+                created_at: None,
+                updated_at: None,
+                ..self
+            }
+        }
+    }
+
+    impl Deref for TempPlugin {
+        type Target = Plugin;
+
+        fn deref(&self) -> &Self::Target {
+            &self.plugin
+        }
+    }
+
+    impl Plugin {
+        pub async fn stub() -> Self {
+            Self::new(&std::env::temp_dir(), Git::init(None).await.unwrap())
+        }
+
+        pub async fn temp() -> TempPlugin {
+            let temp = TempBuilder::new().prefix("temp-gitvol-").tempdir().unwrap();
+            let plugin = Self::new(temp.path(), Git::init(None).await.unwrap());
+            TempPlugin { plugin, temp }
+        }
+
+        pub async fn with_volume(self, volume_name: &str, raw_repo: RawRepo) -> Self {
+            self.create(volume_name, Some(raw_repo)).await.unwrap();
+            self
+        }
+
+        pub async fn with_stub_volume(self) -> Self {
+            self.with_volume(VOLUME_NAME, RawRepo::stub()).await
+        }
+
+        pub async fn test_is_empty_list(&self) -> &Self {
+            let list = self.list().await.unwrap();
+            assert_eq!(list.len(), 0);
+            self
+        }
+
+        pub async fn test_in_list(&self, input_list: Vec<ItemVolume>) -> &Self {
+            let list = self.list().await.unwrap();
+
+            assert_eq!(list.len(), input_list.len());
+
+            for item in input_list {
+                let list_item = list.iter().find(|i| i.name == item.name);
+                assert!(
+                    list_item.is_some(),
+                    "The volume named {} was not found in the list.",
+                    item.name
+                );
+
+                let mountpoint = list_item.and_then(|i| i.mountpoint.clone());
+                assert_eq!(item.mountpoint, mountpoint);
+            }
+
+            self
+        }
+
+        pub async fn test_in_list_by_names(&self, input_list: Vec<&str>) -> &Self {
+            self.test_in_list(
+                input_list
+                    .into_iter()
+                    .map(|name| ItemVolume {
+                        name: name.to_string(),
+                        mountpoint: None,
+                    })
+                    .collect(),
+            )
+            .await
+        }
+
+        pub async fn test_path_is(&self, volume_name: &str, path: Option<PathBuf>) -> &Self {
+            let path_result = self.path(volume_name).await.unwrap();
+            assert_eq!(path_result, path);
+            self
+        }
+
+        pub async fn test_stub_path_is(&self, path: Option<PathBuf>) -> &Self {
+            self.test_path_is(VOLUME_NAME, path).await
+        }
+
+        /// Compares everything except the timestamps, which only have to be present.
+        pub async fn test_get_volume(&self, volume_name: &str, info: VolumeInfo<Status>) -> &Self {
+            let volume = self.get(volume_name).await.unwrap().unwrap();
+            let volume = VolumeInfo {
+                status: volume.status.without_times(),
+                ..volume
+            };
+            assert_eq!(volume, info);
+            self
+        }
+
+        pub async fn test_get_stub_volume(&self, info: VolumeInfo<Status>) -> &Self {
+            self.test_get_volume(VOLUME_NAME, info).await
+        }
+    }
+
+    impl TempPlugin {
+        pub fn configure(self, f: impl FnOnce(Plugin) -> Plugin) -> Self {
+            Self {
+                plugin: f(self.plugin),
+                temp: self.temp,
+            }
+        }
+
+        pub async fn with_temp_volume(self, volume_name: &str, raw_repo: RawRepo) -> Self {
+            let plugin = self.plugin.with_volume(volume_name, raw_repo).await;
+
+            Self {
+                plugin,
+                temp: self.temp,
+            }
+        }
+
+        pub async fn with_stub_test_repo(self) -> (TestRepo, Self) {
+            let test_repo = TestRepo::new();
+            let plugin = self
+                .with_temp_volume(VOLUME_NAME, test_repo.create_raw_repo(None, None, None))
+                .await;
+            (test_repo, plugin)
+        }
+    }
+
+    impl TestRepo {
+        pub fn create_raw_repo(
+            &self,
+            branch: Option<String>,
+            tag: Option<String>,
+            refetch: Option<bool>,
+        ) -> RawRepo {
+            RawRepo {
+                url: Some(self.path().as_os_str().to_str().unwrap().to_string()),
+                branch,
+                tag,
+                refetch,
+                ..Default::default()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::test_mocks::*;
+    use super::*;
+    use crate::{
+        domains::volume::DATA_DIR,
+        services::git::test_mocks::{TestRepo, stub_git},
+    };
+    use rstest::rstest;
+    use std::collections::HashSet;
+    use std::ops::Deref;
+
+    #[rstest]
+    #[case(VolumesError::NonExists("v".into()).into(), "VOLUME_NOT_FOUND")]
+    #[case(VolumesError::AlreadyExists("v".into()).into(), "ALREADY_EXISTS")]
+    #[case(VolumesError::LimitReached(1).into(), "LIMIT_REACHED")]
+    #[case(
+        VolumesError::Url(crate::domains::url::Error::HostNotAllowed("h".into())).into(),
+        "HOST_NOT_ALLOWED"
+    )]
+    #[case(VolumesError::Url(crate::domains::url::Error::Empty).into(), "INVALID_OPTIONS")]
+    #[case(GitError::RefNotFound { url: "u".into(), reference: "r".into() }.into(), "REF_NOT_FOUND")]
+    #[case(GitError::AuthRequired("u".into()).into(), "AUTH_REQUIRED")]
+    #[case(ArchiveError::Io(ErrorKind::NotFound.into()).into(), "ARCHIVE")]
+    #[case(Error::Permissions { path: "/p".into(), kind: ErrorKind::PermissionDenied }, "IO")]
+    #[case(
+        Error::Refetch { name: "v".into(), source: Box::new(GitError::Network { url: "u".into(), reason: "".into() }.into()) },
+        "REFETCH_FAILED"
+    )]
+    #[case(Error::NotMounted("v".into()), "NOT_MOUNTED")]
+    #[case(Error::Volumes(VolumesError::Mounted { name: "v".into(), count: 1 }), "IN_USE")]
+    #[case(Error::NotRefetchable("v".into()), "NOT_REFETCHABLE")]
+    #[case(Error::HooksNotAllowed, "HOOKS_NOT_ALLOWED")]
+    #[case(Error::AuthLost("repo".into()), "AUTH_LOST")]
+    #[case(Error::Inspect { path: "p".into(), kind: ErrorKind::PermissionDenied }, "IO")]
+    #[case(Error::SubpathMissing { name: "v".into(), subpath: "dist".into() }, "SUBPATH_MISSING")]
+    #[case(Error::SubpathEscapes { name: "v".into(), subpath: "dist".into() }, "SUBPATH_ESCAPES")]
+    #[case(Error::NotReady { name: "v".into(), check: "c".into(), timeout: Duration::ZERO }, "NOT_READY")]
+    fn error_codes(#[case] error: Error, #[case] code: &str) {
+        assert_eq!(error.code(), code);
+    }
+
+    #[rstest]
+    #[case(Scope::Local, "local")]
+    #[case(Scope::Global, "global")]
+    #[tokio::test]
+    async fn capabilities_scope(#[case] scope: Scope, #[case] expected: &str) {
+        let plugin = Plugin::stub().await.with_scope(scope);
+        let server = axum_test::TestServer::new(plugin.into_router()).unwrap();
+
+        let response = server.post("/VolumeDriver.Capabilities").await;
+
+        response.assert_status_ok();
+        response.assert_json(&serde_json::json!({ "Capabilities": { "Scope": expected } }));
+    }
+
+    #[tokio::test]
+    async fn activate_implements() {
+        let plugin = Plugin::stub()
+            .await
+            .with_implements(vec![VOLUME_DRIVER.into(), "authz".into()]);
+        let server = axum_test::TestServer::new(plugin.into_router()).unwrap();
+
+        let response = server.post("/Plugin.Activate").await;
+
+        response.assert_status_ok();
+        response.assert_json(&serde_json::json!({ "Implements": ["VolumeDriver", "authz"] }));
+    }
+
+    #[tokio::test]
+    async fn list_empty_initial() {
+        Plugin::stub().await.test_is_empty_list().await;
+    }
+
+    #[tokio::test]
+    async fn path_nonexistent_returns_none() {
+        Plugin::stub().await.test_stub_path_is(None).await;
+    }
+
+    #[tokio::test]
+    async fn get_nonexistent_returns_none() {
+        let plugin = Plugin::stub().await;
+
+        let result = plugin.get(VOLUME_NAME).await.unwrap();
+        assert!(
+            result.is_none(),
+            "Retrieving a non-existent volume should not be an error."
+        );
+    }
+
+    #[tokio::test]
+    async fn get_broken_volume_errors() {
+        let (_test_repo, plugin) = Plugin::temp().await.with_stub_test_repo().await;
+        let temp = tempfile::tempdir().unwrap();
+        let file = temp.path().join("file");
+        std::fs::write(&file, "").unwrap();
+        plugin.volumes.try_write(VOLUME_NAME).await.unwrap().path = Some(file.join("checkout"));
+
+        let result = plugin.get(VOLUME_NAME).await;
+        assert!(
+            matches!(result, Err(Error::Inspect { .. })),
+            "A checkout that can not be inspected should be an error."
+        );
+    }
+
+    #[rstest]
+    #[case(RawRepo::stub())]
+    #[case(RawRepo { branch: Some("some_branch".into()), ..RawRepo::stub() })]
+    #[case(RawRepo { tag: Some("som-tag".into()), ..RawRepo::stub() })]
+    #[case(RawRepo { refetch: Some(true), ..RawRepo::stub() })]
+    #[tokio::test]
+    async fn create_success_new_volume(#[case] raw_repo: RawRepo) {
+        Plugin::stub()
+            .await
+            .with_volume(VOLUME_NAME, raw_repo)
+            .await
+            .test_in_list_by_names(vec![VOLUME_NAME])
+            .await
+            .test_stub_path_is(None)
+            .await
+            .test_get_stub_volume(VolumeInfo {
+                status: VolumeStatus::Created.into(),
+                mountpoint: None,
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn create_duplicate_name_error() {
+        let plugin = Plugin::stub().await.with_stub_volume().await;
+
+        let second_creating = plugin
+            .create(
+                VOLUME_NAME,
+                Some(RawRepo {
+                    branch: Some("other".into()),
+                    ..RawRepo::stub()
+                }),
+            )
+            .await;
+        assert!(
+            second_creating.is_err(),
+            "Recreating the volume with other options should result in an error."
+        );
+
+        let error = second_creating.unwrap_err();
+        assert!(matches!(
+            error,
+            Error::Volumes(VolumesError::AlreadyExists(_))
+        ));
+
+        plugin.test_in_list_by_names(vec![VOLUME_NAME]).await;
+    }
+
+    #[rstest]
+    #[case("", Some(RawRepo::stub()))]
+    #[case("     ", Some(RawRepo::stub()))]
+    #[case(VOLUME_NAME, None)]
+    #[case(VOLUME_NAME, Some(RawRepo::default()))]
+    #[case(VOLUME_NAME, Some(RawRepo { branch: Some("some_branch".into()), tag: Some("some_tag".into()), ..RawRepo::stub()}))]
+    #[case(VOLUME_NAME, Some(RawRepo { url: Some("ftp://host/path-to-git-repo".into()), ..RawRepo::default()}))]
+    #[tokio::test]
+    async fn create_invalid_params_error(
+        #[case] volume_name: &str,
+        #[case] raw_repo: Option<RawRepo>,
+    ) {
+        let plugin = Plugin::stub().await;
+
+        let result = plugin.create(volume_name, raw_repo.clone()).await;
+        assert!(
+            result.is_err(),
+            "Creating a volume with incorrect parameters should result in an error. name={:?}; options={:?}",
+            volume_name,
+            raw_repo
+        );
+
+        let error = result.unwrap_err();
+        assert!(matches!(error, Error::Volumes(_)));
+        plugin.test_is_empty_list().await;
+    }
+
+    #[tokio::test]
+    async fn list_multiple_volumes() {
+        Plugin::stub()
+            .await
+            .with_stub_volume()
+            .await
+            .with_volume("other_volume", RawRepo::stub())
+            .await
+            .test_in_list_by_names(vec![VOLUME_NAME, "other_volume"])
+            .await;
+    }
+
+    #[rstest]
+    #[case::valid(None, None, None, |r: &Result<Repo, Error>| r.is_ok())]
+    #[case::valid_branch(Some("master"), None, None, |r: &Result<Repo, Error>| r.is_ok())]
+    #[case::missing_ref(Some("missing"), None, None, |r: &Result<Repo, Error>| matches!(r, Err(Error::Git(GitError::RefNotFound { .. }))))]
+    #[case::bad_scheme(None, None, Some("ftp://example.com/repo.git"), |r: &Result<Repo, Error>| matches!(r, Err(Error::Volumes(VolumesError::Volume(_)))))]
+    #[case::branch_and_tag(Some("master"), Some("v1"), None, |r: &Result<Repo, Error>| matches!(r, Err(Error::Volumes(VolumesError::Volume(_)))))]
+    #[tokio::test]
+    async fn validate_options(
+        #[case] branch: Option<&str>,
+        #[case] tag: Option<&str>,
+        #[case] url: Option<&str>,
+        #[case] expect: fn(&Result<Repo, Error>) -> bool,
+    ) {
+        let test_repo = TestRepo::new();
+        let mut raw =
+            test_repo.create_raw_repo(branch.map(str::to_string), tag.map(str::to_string), None);
+        if let Some(url) = url {
+            raw.url = Some(url.to_string());
+        }
+        let plugin = Plugin::temp().await;
+
+        let result = plugin.validate(raw, true).await;
+
+        assert!(expect(&result), "{:?}", result.err());
+        plugin.test_is_empty_list().await;
+    }
+
+    #[tokio::test]
+    async fn validate_skips_remote_by_default() {
+        let plugin = Plugin::temp().await;
+        let raw = RawRepo::from_url("https://example.invalid/repo.git");
+
+        assert!(plugin.validate(raw, false).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn list_filtered_by_status() {
+        let test_repo = TestRepo::new();
+        let plugin = Plugin::temp()
+            .await
+            .with_temp_volume("created", test_repo.create_raw_repo(None, None, None))
+            .await
+            .with_temp_volume("mounted", test_repo.create_raw_repo(None, None, None))
+            .await
+            .with_temp_volume("cleared", test_repo.create_raw_repo(None, None, None))
+            .await;
+        let mountpoint = plugin.mount("mounted", "id-1").await.unwrap();
+        plugin.mount("cleared", "id-2").await.unwrap();
+        plugin.unmount("cleared", "id-2").await.unwrap();
+
+        let names = |list: Vec<ItemVolume>| {
+            let mut names: Vec<_> = list.into_iter().map(|v| v.name).collect();
+            names.sort();
+            names
+        };
+        assert_eq!(
+            plugin.list_by_status(Some("clonned")).await,
+            vec![ItemVolume {
+                name: "mounted".into(),
+                mountpoint: Some(mountpoint),
+            }]
+        );
+        assert_eq!(
+            names(plugin.list_by_status(Some("created")).await),
+            ["created"]
+        );
+        assert_eq!(
+            names(plugin.list_by_status(Some("cleared")).await),
+            ["cleared"]
+        );
+        assert!(plugin.list_by_status(Some("failed")).await.is_empty());
+        assert_eq!(
+            names(plugin.list_by_status(None).await),
+            ["cleared", "created", "mounted"]
+        );
+    }
+
+    #[tokio::test]
+    async fn mount_clones_again_after_directory_removed() {
+        let (test_repo, plugin) = Plugin::temp().await.with_stub_test_repo().await;
+        let mountpoint = plugin.mount(VOLUME_NAME, "id-1").await.unwrap();
+        std::fs::remove_dir_all(&mountpoint).unwrap();
+
+        let remounted = plugin.mount(VOLUME_NAME, "id-2").await.unwrap();
+
+        assert_eq!(remounted, mountpoint);
+        test_repo.test_is_default_branch(&remounted);
+        let info = plugin.get(VOLUME_NAME).await.unwrap().unwrap();
+        assert_eq!(info.status.status, VolumeStatus::Clonned);
+        assert_eq!(info.status.commit, Some(test_repo.head("master")));
+        assert_eq!(info.status.available, Some(true));
+    }
+
+    #[tokio::test]
+    async fn path_after_mount_returns_some() {
+        let (_g, plugin) = Plugin::temp().await.with_stub_test_repo().await;
+
+        let mountpoint = plugin.mount(VOLUME_NAME, "id-123").await.unwrap();
+
+        plugin.test_stub_path_is(Some(mountpoint)).await;
+    }
+
+    #[tokio::test]
+    async fn get_created_unmounted_status() {
+        let (_g, plugin) = Plugin::temp().await.with_stub_test_repo().await;
+
+        let created = plugin.get(VOLUME_NAME).await.unwrap().unwrap();
+        assert_eq!(created.status.without_times(), VolumeStatus::Created.into());
+
+        plugin.mount(VOLUME_NAME, "id-123").await.unwrap();
+        plugin.unmount(VOLUME_NAME, "id-123").await.unwrap();
+
+        let cleared = plugin.get(VOLUME_NAME).await.unwrap().unwrap();
+        assert_eq!(cleared.status.without_times(), VolumeStatus::Cleared.into());
+    }
+
+    #[tokio::test]
+    async fn get_after_mount_status_clonned() {
+        let (test_repo, plugin) = Plugin::temp().await.with_stub_test_repo().await;
+
+        let mountpoint = plugin.mount(VOLUME_NAME, "id-123").await.unwrap();
+
+        assert!(mountpoint.exists());
+        plugin
+            .test_get_stub_volume(VolumeInfo {
+                mountpoint: Some(mountpoint.clone()),
+                status: Status {
+                    status: VolumeStatus::Clonned,
+                    commit: Some(test_repo.head("master")),
+                    branch: Some("master".into()),
+                    size_bytes: Some(disk::dir_size(&mountpoint).await.unwrap()),
+                    available: Some(true),
+                    containers: 1,
+                    created_at: None,
+                    updated_at: None,
+                },
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn get_reports_containers() {
+        let (_g, plugin) = Plugin::temp().await.with_stub_test_repo().await;
+        let containers = async || {
+            let info = plugin.get(VOLUME_NAME).await.unwrap().unwrap();
+            info.status.containers
+        };
+
+        plugin.mount(VOLUME_NAME, "id-1").await.unwrap();
+        plugin.mount(VOLUME_NAME, "id-2").await.unwrap();
+        assert_eq!(containers().await, 2);
+
+        plugin.unmount(VOLUME_NAME, "id-1").await.unwrap();
+        assert_eq!(containers().await, 1);
+        plugin.unmount(VOLUME_NAME, "id-2").await.unwrap();
+        assert_eq!(containers().await, 0);
+    }
+
+    #[tokio::test]
+    async fn get_reports_size() {
+        let (_g, plugin) = Plugin::temp().await.with_stub_test_repo().await;
+
+        assert_eq!(
+            plugin
+                .get(VOLUME_NAME)
+                .await
+                .unwrap()
+                .unwrap()
+                .status
+                .size_bytes,
+            None
+        );
+
+        let mountpoint = plugin.mount(VOLUME_NAME, "id").await.unwrap();
+        let initial = plugin
+            .get(VOLUME_NAME)
+            .await
+            .unwrap()
+            .unwrap()
+            .status
+            .size_bytes;
+        let initial = initial.unwrap();
+
+        std::fs::create_dir(mountpoint.join("nested")).unwrap();
+        std::fs::write(mountpoint.join("first"), [0u8; 1024]).unwrap();
+        std::fs::write(mountpoint.join("nested/second"), [0u8; 4096]).unwrap();
+
+        let size = plugin
+            .get(VOLUME_NAME)
+            .await
+            .unwrap()
+            .unwrap()
+            .status
+            .size_bytes;
+        assert_eq!(size, Some(initial + 1024 + 4096));
+    }
+
+    #[tokio::test]
+    async fn get_response_shape() {
+        use axum_test::TestServer;
+        use serde_json::{Value, json};
+
+        let (test_repo, plugin) = Plugin::temp().await.with_stub_test_repo().await;
+        let server = TestServer::new(plugin.deref().clone().into_router()).unwrap();
+        let get = async || {
+            let response = server
+                .post("/VolumeDriver.Get")
+                .json(&json!({ "Name": VOLUME_NAME }))
+                .await;
+            response.assert_status_ok();
+            response.json::<Value>()
+        };
+
+        let created = get().await;
+        let volume = &created["Volume"];
+        assert_eq!(volume["Name"], VOLUME_NAME);
+        assert!(volume.get("Mountpoint").is_none());
+        let status = volume["Status"].as_object().unwrap();
+        let mut keys: Vec<_> = status.keys().map(String::as_str).collect();
+        keys.sort();
+        assert_eq!(
+            keys,
+            [
+                "Available",
+                "Containers",
+                "CreatedAt",
+                "Status",
+                "UpdatedAt"
+            ]
+        );
+        assert_eq!(status["Status"], "Created");
+        assert_eq!(status["Available"], false);
+        assert_eq!(status["Containers"], 0);
+
+        let mountpoint = plugin.mount(VOLUME_NAME, "id").await.unwrap();
+        let mounted = get().await;
+        let volume = &mounted["Volume"];
+        assert_eq!(volume["Mountpoint"], mountpoint.to_str().unwrap());
+        let status = &volume["Status"];
+        assert_eq!(status["Status"], "Clonned");
+        assert_eq!(status["Available"], true);
+        assert_eq!(status["Containers"], 1);
+        assert_eq!(status["Commit"], test_repo.head("master"));
+        assert_eq!(status["Branch"], "master");
+        assert!(status["Size"].is_u64());
+        assert!(status["CreatedAt"].as_str().unwrap().ends_with('Z'));
+    }
+
+    #[tokio::test]
+    async fn refetch_updates_commit() {
+        let test_repo = TestRepo::new();
+        let plugin = Plugin::temp()
+            .await
+            .with_temp_volume(
+                VOLUME_NAME,
+                test_repo.create_raw_repo(None, None, Some(true)),
+            )
+            .await;
+
+        plugin.mount(VOLUME_NAME, "id-1").await.unwrap();
+        test_repo.change("master", "changed value");
+        plugin.mount(VOLUME_NAME, "id-2").await.unwrap();
+
+        let info = plugin.get(VOLUME_NAME).await.unwrap().unwrap();
+        assert_eq!(info.status.commit, Some(test_repo.head("master")));
+    }
+
+    #[tokio::test]
+    async fn remove_nonexistent_by_empty_ok() {
+        let plugin = Plugin::stub().await;
+        let result = plugin.remove("other_volume").await;
+        assert!(result.is_ok());
+
+        plugin.test_is_empty_list().await;
+    }
+
+    #[tokio::test]
+    async fn remove_nonexistent_with_other_volumes_ok() {
+        let plugin = Plugin::stub().await.with_stub_volume().await;
+
+        let result = plugin.remove("other_volume").await;
+        assert!(result.is_ok());
+
+        plugin.test_in_list_by_names(vec![VOLUME_NAME]).await;
+    }
+
+    #[tokio::test]
+    async fn remove_existing_unmounted_ok() {
+        let plugin = Plugin::stub().await.with_stub_volume().await;
+
+        let result = plugin.remove(VOLUME_NAME).await;
+        assert!(result.is_ok());
+
+        plugin
+            .test_is_empty_list()
+            .await
+            .test_stub_path_is(None)
+            .await;
+    }
+
+    #[tokio::test]
+    async fn remove_mounted_in_use() {
+        let (_g, plugin) = Plugin::temp().await.with_stub_test_repo().await;
+
+        let mountpoint = plugin.mount(VOLUME_NAME, "id-1").await.unwrap();
+        plugin.mount(VOLUME_NAME, "id-2").await.unwrap();
+        let error = plugin.remove(VOLUME_NAME).await.unwrap_err();
+        assert!(
+            matches!(&error, Error::Volumes(VolumesError::Mounted { name, count: 2 }) if name == VOLUME_NAME),
+            "{error:?}"
+        );
+        assert!(mountpoint.exists());
+
+        plugin.unmount(VOLUME_NAME, "id-1").await.unwrap();
+        plugin.unmount(VOLUME_NAME, "id-2").await.unwrap();
+        plugin.remove(VOLUME_NAME).await.unwrap();
+
+        plugin.test_is_empty_list().await;
+        assert!(!mountpoint.exists());
+    }
+
+    #[tokio::test]
+    async fn mount_first_time_clones_repo() {
+        let (test_repo, plugin) = Plugin::temp().await.with_stub_test_repo().await;
+
+        let mountpoint = plugin.mount(VOLUME_NAME, "id").await.unwrap();
+
+        TestRepo::test_is_not_git(&mountpoint);
+        test_repo.test_is_default_branch(&mountpoint);
+    }
+
+    #[tokio::test]
+    async fn mount_subpath() {
+        let test_repo = TestRepo::new().with_files(&[("dist/index.html", "index")]);
+        let plugin = Plugin::temp()
+            .await
+            .with_temp_volume(
+                VOLUME_NAME,
+                RawRepo {
+                    mount_subpath: Some("dist".into()),
+                    ..test_repo.create_raw_repo(None, None, None)
+                },
+            )
+            .await;
+
+        let mountpoint = plugin.mount(VOLUME_NAME, "id-1").await.unwrap();
+        assert!(mountpoint.ends_with("dist"));
+        assert_eq!(
+            std::fs::read_to_string(mountpoint.join("index.html")).unwrap(),
+            "index"
+        );
+        assert_eq!(plugin.mount(VOLUME_NAME, "id-2").await.unwrap(), mountpoint);
+        plugin.test_stub_path_is(Some(mountpoint.clone())).await;
+
+        // The whole clone goes on unmount, not only the mounted directory.
+        let clone = mountpoint.parent().unwrap().to_path_buf();
+        test_repo.test_is_default_branch(&clone);
+        plugin.unmount(VOLUME_NAME, "id-1").await.unwrap();
+        plugin.unmount(VOLUME_NAME, "id-2").await.unwrap();
+        assert!(!clone.exists());
+    }
+
+    #[tokio::test]
+    async fn mount_missing_subpath() {
+        let (test_repo, plugin) = Plugin::temp().await.with_stub_test_repo().await;
+        plugin
+            .update(
+                VOLUME_NAME,
+                RawRepo {
+                    mount_subpath: Some("dist".into()),
+                    ..test_repo.create_raw_repo(None, None, None)
+                },
+            )
+            .await
+            .unwrap();
+
+        let error = plugin.mount(VOLUME_NAME, "id").await.unwrap_err();
+        assert!(matches!(error, Error::SubpathMissing { .. }), "{error:?}");
+        plugin.test_stub_path_is(None).await;
+        let volume = plugin.volumes.try_read(VOLUME_NAME).await.unwrap();
+        assert!(!volume.data_path(&plugin.base_path).exists());
+    }
+
+    #[rstest]
+    #[case::host("/", false)]
+    #[case::parent("..", false)]
+    #[case::inside("build", true)]
+    #[tokio::test]
+    async fn mount_symlinked_subpath(#[case] target: &str, #[case] allowed: bool) {
+        let test_repo = TestRepo::new()
+            .with_files(&[("build/index.html", "index")])
+            .with_symlink("dist", target);
+        let plugin = Plugin::temp()
+            .await
+            .with_temp_volume(
+                VOLUME_NAME,
+                RawRepo {
+                    mount_subpath: Some("dist".into()),
+                    ..test_repo.create_raw_repo(None, None, None)
+                },
+            )
+            .await;
+
+        let mounted = plugin.mount(VOLUME_NAME, "id").await;
+
+        if allowed {
+            let mountpoint = mounted.unwrap();
+            assert_eq!(
+                std::fs::read_to_string(mountpoint.join("index.html")).unwrap(),
+                "index"
+            );
+        } else {
+            let error = mounted.unwrap_err();
+            assert!(matches!(error, Error::SubpathEscapes { .. }), "{error:?}");
+            plugin.test_stub_path_is(None).await;
+            let volume = plugin.volumes.try_read(VOLUME_NAME).await.unwrap();
+            assert!(!volume.data_path(&plugin.base_path).exists());
+        }
+    }
+
+    #[tokio::test]
+    async fn mount_when_already_mounted_no_clone() {
+        let (_g, plugin) = Plugin::temp().await.with_stub_test_repo().await;
+
+        let first_mountpoint = plugin.mount(VOLUME_NAME, "id-1").await.unwrap();
+        let second_mountpoint = plugin.mount(VOLUME_NAME, "id-2").await.unwrap();
+
+        assert_eq!(first_mountpoint, second_mountpoint);
+    }
+
+    #[tokio::test]
+    async fn mount_with_branch() {
+        let test_repo = TestRepo::new().with_branch("develop");
+        let plugin = Plugin::temp()
+            .await
+            .with_temp_volume(
+                VOLUME_NAME,
+                test_repo.create_raw_repo(Some("develop".into()), None, None),
+            )
+            .await;
+
+        let mountpoint = plugin.mount(VOLUME_NAME, "id").await.unwrap();
+        TestRepo::test_is_branch(&mountpoint, "develop");
+    }
+
+    #[tokio::test]
+    async fn mount_with_tag() {
+        let test_repo = TestRepo::new().with_tag("v1");
+        let plugin = Plugin::temp()
+            .await
+            .with_temp_volume(
+                VOLUME_NAME,
+                test_repo.create_raw_repo(None, Some("v1".into()), None),
+            )
+            .await;
+
+        let mountpoint = plugin.mount(VOLUME_NAME, "id").await.unwrap();
+        TestRepo::test_is_tag(&mountpoint, "v1");
+    }
+
+    #[tokio::test]
+    async fn mount_with_refetch() {
+        let branch_name = "some_branch";
+        let test_repo = TestRepo::new().with_branch(branch_name);
+        let plugin = Plugin::temp()
+            .await
+            .with_temp_volume(
+                VOLUME_NAME,
+                test_repo.create_raw_repo(Some(branch_name.into()), None, Some(true)),
+            )
+            .await;
+
+        let mountpoint = plugin.mount(VOLUME_NAME, "id-1").await.unwrap();
+        TestRepo::test_is_git(&mountpoint);
+        TestRepo::test_is_branch(&mountpoint, branch_name);
+
+        test_repo.change(branch_name, "changed value");
+
+        plugin.mount(VOLUME_NAME, "id-2").await.unwrap();
+        TestRepo::test_is_changed(&mountpoint, branch_name, "changed value");
+    }
+
+    #[tokio::test]
+    async fn mount_tag_with_refetch() {
+        let test_repo = TestRepo::new().with_tag("v1");
+        let plugin = Plugin::temp()
+            .await
+            .with_temp_volume(
+                VOLUME_NAME,
+                test_repo.create_raw_repo(None, Some("v1".into()), Some(true)),
+            )
+            .await;
+
+        let mountpoint = plugin.mount(VOLUME_NAME, "id-1").await.unwrap();
+        TestRepo::test_is_tag(&mountpoint, "v1");
+        let commit = test_repo.head("v1");
+        let content = std::fs::read_to_string(mountpoint.join("branch-master")).unwrap();
+        test_repo.change("master", "changed value");
+        // Any git call to the remote would fail from now on.
+        std::fs::remove_dir_all(test_repo.path()).unwrap();
+
+        let remounted = plugin.mount(VOLUME_NAME, "id-2").await.unwrap();
+        assert_eq!(remounted, mountpoint);
+        TestRepo::test_is_tag(&mountpoint, "v1");
+        assert_eq!(
+            std::fs::read_to_string(mountpoint.join("branch-master")).unwrap(),
+            content
+        );
+        assert_eq!(plugin.refresh(VOLUME_NAME).await.unwrap(), commit);
+        let status = plugin.get(VOLUME_NAME).await.unwrap().unwrap().status;
+        assert_eq!(status.commit, Some(commit));
+    }
+
+    #[rstest]
+    #[case::stripped(None, None, false)]
+    #[case::keep_git(Some(true), None, false)]
+    #[case::refetch(None, Some(true), true)]
+    #[case::keep_git_and_refetch(Some(true), Some(true), true)]
+    #[tokio::test]
+    async fn mount_keep_git_and_refetch(
+        #[case] keep_git: Option<bool>,
+        #[case] refetch: Option<bool>,
+        #[case] updated: bool,
+    ) {
+        let test_repo = TestRepo::new();
+        let plugin = Plugin::temp()
+            .await
+            .with_temp_volume(
+                VOLUME_NAME,
+                RawRepo {
+                    keep_git,
+                    ..test_repo.create_raw_repo(None, None, refetch)
+                },
+            )
+            .await;
+
+        let mountpoint = plugin.mount(VOLUME_NAME, "id-1").await.unwrap();
+        if keep_git.is_some() || refetch.is_some() {
+            TestRepo::test_is_git(&mountpoint);
+        } else {
+            TestRepo::test_is_not_git(&mountpoint);
+        }
+
+        test_repo.change("master", "changed value");
+        plugin.mount(VOLUME_NAME, "id-2").await.unwrap();
+        let content = std::fs::read_to_string(mountpoint.join("branch-master")).unwrap();
+        assert_eq!(content == "changed value", updated);
+    }
+
+    #[rstest]
+    #[case::lenient(false)]
+    #[case::strict(true)]
+    #[tokio::test]
+    async fn mount_after_failed_refetch(#[case] strict: bool) {
+        let test_repo = TestRepo::new();
+        let plugin = Plugin::temp()
+            .await
+            .configure(|plugin| plugin.with_strict_refetch(strict))
+            .with_temp_volume(
+                VOLUME_NAME,
+                test_repo.create_raw_repo(None, None, Some(true)),
+            )
+            .await;
+        let mountpoint = plugin.mount(VOLUME_NAME, "id-1").await.unwrap();
+        let commit = test_repo.head("master");
+        std::fs::remove_dir_all(test_repo.path()).unwrap();
+
+        let result = plugin.mount(VOLUME_NAME, "id-2").await;
+
+        if strict {
+            let error = result.unwrap_err();
+            assert!(
+                matches!(&error, Error::Refetch { name, source } if name == VOLUME_NAME && matches!(**source, Error::Git(_))),
+                "{error:?}"
+            );
+        } else {
+            assert_eq!(result.unwrap(), mountpoint);
+        }
+        test_repo.test_is_default_branch(&mountpoint);
+        let volume = plugin.volumes.try_read(VOLUME_NAME).await.unwrap();
+        assert_eq!(volume.path.as_ref(), Some(&mountpoint));
+        assert_eq!(volume.commit, Some(commit));
+        assert_eq!(volume.containers.contains("id-2"), !strict);
+    }
+
+    #[tokio::test]
+    async fn mount_with_refetch_debounce() {
+        let branch_name = "some_branch";
+        let test_repo = TestRepo::new().with_branch(branch_name);
+        let plugin = Plugin::temp()
+            .await
+            .configure(|plugin| plugin.with_refetch_debounce(Duration::from_secs(60)))
+            .with_temp_volume(
+                VOLUME_NAME,
+                test_repo.create_raw_repo(Some(branch_name.into()), None, Some(true)),
+            )
+            .await;
+
+        let mountpoint = plugin.mount(VOLUME_NAME, "id-1").await.unwrap();
+        test_repo.change(branch_name, "changed value");
+
+        plugin.mount(VOLUME_NAME, "id-2").await.unwrap();
+        TestRepo::test_is_changed(&mountpoint, branch_name, "");
+    }
+
+    #[rstest]
+    #[case("branch-master")]
+    #[case("cmd:test -f branch-master")]
+    #[tokio::test]
+    async fn mount_waits_for_ready_check(#[case] ready_check: &str) {
+        let test_repo = TestRepo::new();
+        let plugin = Plugin::temp()
+            .await
+            .configure(|plugin| plugin.with_allow_hooks(true))
+            .with_temp_volume(
+                VOLUME_NAME,
+                RawRepo {
+                    ready_check: Some(ready_check.into()),
+                    ..test_repo.create_raw_repo(None, None, None)
+                },
+            )
+            .await;
+
+        let mountpoint = plugin.mount(VOLUME_NAME, "id").await.unwrap();
+        test_repo.test_is_default_branch(&mountpoint);
+    }
+
+    #[rstest]
+    #[case("missing-file")]
+    #[case("cmd:test -f missing-file")]
+    #[tokio::test]
+    async fn mount_fails_when_not_ready(#[case] ready_check: &str) {
+        let test_repo = TestRepo::new();
+        let plugin = Plugin::temp()
+            .await
+            .configure(|plugin| {
+                plugin
+                    .with_allow_hooks(true)
+                    .with_ready_timeout(Duration::from_millis(500))
+            })
+            .with_temp_volume(
+                VOLUME_NAME,
+                RawRepo {
+                    ready_check: Some(ready_check.into()),
+                    ..test_repo.create_raw_repo(None, None, None)
+                },
+            )
+            .await;
+
+        let error = plugin.mount(VOLUME_NAME, "id").await.unwrap_err();
+        assert!(matches!(error, Error::NotReady { .. }), "{error:?}");
+
+        plugin.test_stub_path_is(None).await;
+        let volume = plugin.volumes.try_read(VOLUME_NAME).await.unwrap();
+        assert!(!volume.dir(&plugin.base_path).join(DATA_DIR).exists());
+    }
+
+    #[tokio::test]
+    async fn mount_runs_post_clone_hook() {
+        let test_repo = TestRepo::new();
+        let plugin = Plugin::temp()
+            .await
+            .configure(|plugin| plugin.with_allow_hooks(true))
+            .with_temp_volume(
+                VOLUME_NAME,
+                RawRepo {
+                    post_clone: Some("test -f branch-master && touch hook-marker".into()),
+                    ..test_repo.create_raw_repo(None, None, None)
+                },
+            )
+            .await;
+
+        let mountpoint = plugin.mount(VOLUME_NAME, "id").await.unwrap();
+        assert!(mountpoint.join("hook-marker").is_file());
+    }
+
+    #[tokio::test]
+    async fn mount_fails_when_post_clone_hook_fails() {
+        let test_repo = TestRepo::new();
+        let plugin = Plugin::temp()
+            .await
+            .configure(|plugin| plugin.with_allow_hooks(true))
+            .with_temp_volume(
+                VOLUME_NAME,
+                RawRepo {
+                    post_clone: Some("echo broken build >&2; exit 3".into()),
+                    ..test_repo.create_raw_repo(None, None, None)
+                },
+            )
+            .await;
+
+        let error = plugin.mount(VOLUME_NAME, "id").await.unwrap_err();
+        assert!(matches!(error, Error::Hook(..)), "{error:?}");
+        assert!(error.to_string().contains("broken build"), "{error}");
+
+        plugin.test_stub_path_is(None).await;
+        let volume = plugin.volumes.try_read(VOLUME_NAME).await.unwrap();
+        assert!(!volume.dir(&plugin.base_path).join(DATA_DIR).exists());
+    }
+
+    #[tokio::test]
+    async fn post_clone_hook_requires_allow_hooks() {
+        let plugin = Plugin::stub().await;
+        let raw = RawRepo {
+            post_clone: Some("touch hook-marker".into()),
+            ..RawRepo::stub()
+        };
+
+        let error = plugin
+            .create(VOLUME_NAME, Some(raw.clone()))
+            .await
+            .unwrap_err();
+        assert!(matches!(error, Error::HooksNotAllowed), "{error:?}");
+        let error = plugin.validate(raw, false).await.unwrap_err();
+        assert!(matches!(error, Error::HooksNotAllowed), "{error:?}");
+        plugin.test_is_empty_list().await;
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::test_mocks::*;
-    use super::*;
-    use rstest::rstest;
-    use std::ops::Deref;
+    #[tokio::test]
+    async fn ready_check_command_requires_allow_hooks() {
+        let (_g, plugin) = Plugin::temp().await.with_stub_test_repo().await;
+        let raw = RawRepo {
+            ready_check: Some("cmd:touch ready-marker".into()),
+            ..RawRepo::stub()
+        };
 
-    use crate::services::git::test_mocks::TestRepo;
+        let error = plugin.create("other", Some(raw.clone())).await.unwrap_err();
+        assert!(matches!(error, Error::HooksNotAllowed), "{error:?}");
+        let error = plugin.validate(raw.clone(), false).await.unwrap_err();
+        assert!(matches!(error, Error::HooksNotAllowed), "{error:?}");
+        let error = plugin.update(VOLUME_NAME, raw).await.unwrap_err();
+        assert!(matches!(error, Error::HooksNotAllowed), "{error:?}");
+
+        let file_check = RawRepo {
+            ready_check: Some("dist/index.html".into()),
+            ..RawRepo::stub()
+        };
+        plugin.validate(file_check, false).await.unwrap();
+    }
 
     #[tokio::test]
-    async fn list_empty_initial() {
-        Plugin::stub().await.test_is_empty_list().await;
+    async fn mount_rejects_ready_check_command_without_hooks() {
+        let test_repo = TestRepo::new();
+        // Created while hooks were allowed, then the plugin is restarted without them.
+        let plugin = Plugin::temp()
+            .await
+            .configure(|plugin| plugin.with_allow_hooks(true))
+            .with_temp_volume(
+                VOLUME_NAME,
+                RawRepo {
+                    ready_check: Some("cmd:touch ready-marker".into()),
+                    ..test_repo.create_raw_repo(None, None, None)
+                },
+            )
+            .await
+            .configure(|plugin| plugin.with_allow_hooks(false));
+
+        let error = plugin.mount(VOLUME_NAME, "id").await.unwrap_err();
+        assert!(matches!(error, Error::HooksNotAllowed), "{error:?}");
+
+        plugin.test_stub_path_is(None).await;
+        let volume = plugin.volumes.try_read(VOLUME_NAME).await.unwrap();
+        assert!(!volume.dir(&plugin.base_path).join(DATA_DIR).exists());
     }
 
     #[tokio::test]
-    async fn path_nonexistent_returns_none() {
-        Plugin::stub().await.test_stub_path_is(None).await;
+    async fn create_request_saves_state() {
+        use crate::{domains::repo::test::REPO_URL, services::store::REPO_FILE};
+        use axum_test::TestServer;
+        use serde_json::json;
+
+        let temp = tempfile::tempdir().unwrap();
+        let plugin = Plugin::load(temp.path(), Git::init(None).await.unwrap())
+            .await
+            .unwrap();
+        let server = TestServer::new(plugin.clone().into_router()).unwrap();
+
+        server
+            .post("/VolumeDriver.Create")
+            .json(&json!({ "Name": VOLUME_NAME, "Opts": { "url": REPO_URL } }))
+            .await
+            .assert_status_ok();
+
+        let volume = plugin.volumes.try_read(VOLUME_NAME).await.unwrap();
+        let state = volume.dir(temp.path()).join(REPO_FILE);
+        assert!(state.is_file(), "{state:?}");
     }
 
     #[tokio::test]
-    async fn get_nonexistent_returns_error() {
+    async fn create_request_with_unknown_options() {
+        use crate::domains::repo::test::REPO_URL;
+        use crate::split_tracing::test_mocks::LogCapture;
+        use axum_test::TestServer;
+        use serde_json::json;
+
+        let (logs, _guard) = LogCapture::start();
         let plugin = Plugin::stub().await;
+        let server = TestServer::new(plugin.clone().into_router()).unwrap();
+
+        let response = server
+            .post("/VolumeDriver.Create")
+            .json(&json!({
+                "Name": VOLUME_NAME,
+                "Opts": { "url": REPO_URL, "tag": "v1", "depth": "1", "future": "2", "unknown": "value" }
+            }))
+            .await;
 
-        let result = plugin.get(VOLUME_NAME).await;
+        response.assert_status_ok();
+        response.assert_json(&json!({}));
+        let volume = plugin.volumes.try_read(VOLUME_NAME).await.unwrap();
+        assert_eq!(volume.repo.branch.as_deref(), Some("v1"));
+        assert_eq!(volume.repo.depth, Some(1));
         assert!(
-            result.is_err(),
-            "Retrieving a non-existent volume should result in an error."
+            logs.lines()
+                .iter()
+                .any(|line| line.contains("unknown repository options")
+                    && line.contains("[\"future\", \"unknown\"]")),
+            "{:?}",
+            logs.lines()
         );
-
-        let error = result.unwrap_err();
-        assert!(matches!(error, Error::Volumes(VolumesError::NonExists(_))));
     }
 
     #[rstest]
-    #[case(RawRepo::stub())]
-    #[case(RawRepo { branch: Some("some_branch".into()), ..RawRepo::stub() })]
-    #[case(RawRepo { tag: Some("som-tag".into()), ..RawRepo::stub() })]
-    #[case(RawRepo { refetch: Some("true".into()), ..RawRepo::stub() })]
+    #[case::shared(false)]
+    #[case::isolated(true)]
     #[tokio::test]
-    async fn create_success_new_volume(#[case] raw_repo: RawRepo) {
-        Plugin::stub()
+    async fn containers_survive_restart(#[case] isolated: bool) {
+        let test_repo = TestRepo::new();
+        let temp = tempfile::tempdir().unwrap();
+        let raw_repo = RawRepo {
+            isolated: Some(isolated),
+            ..test_repo.create_raw_repo(None, None, None)
+        };
+        let plugin = Plugin::load(temp.path(), Git::init(None).await.unwrap())
             .await
+            .unwrap()
             .with_volume(VOLUME_NAME, raw_repo)
+            .await;
+        let first = plugin.mount(VOLUME_NAME, "id-1").await.unwrap();
+        let second = plugin.mount(VOLUME_NAME, "id-2").await.unwrap();
+
+        let restarted = Plugin::load(temp.path(), Git::init(None).await.unwrap())
             .await
-            .test_in_list_by_names(vec![VOLUME_NAME])
+            .unwrap();
+
+        restarted.unmount(VOLUME_NAME, "id-1").await.unwrap();
+        assert!(second.exists());
+        TestRepo::test_is_branch(&second, "master");
+        let error = restarted.remove(VOLUME_NAME).await.unwrap_err();
+        assert!(
+            matches!(
+                error,
+                Error::Volumes(VolumesError::Mounted { count: 1, .. })
+            ),
+            "{error:?}"
+        );
+
+        restarted.unmount(VOLUME_NAME, "id-2").await.unwrap();
+        assert!(!first.exists());
+        assert!(!second.exists());
+        restarted.remove(VOLUME_NAME).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn volumes_survive_restart() {
+        let test_repo = TestRepo::new();
+        let temp = tempfile::tempdir().unwrap();
+        let plugin = Plugin::load(temp.path(), Git::init(None).await.unwrap())
+            .await
+            .unwrap()
+            .with_volume(VOLUME_NAME, test_repo.create_raw_repo(None, None, None))
+            .await
+            .with_volume("created", test_repo.create_raw_repo(None, None, None))
+            .await
+            .with_volume("removed", test_repo.create_raw_repo(None, None, None))
+            .await;
+        let mountpoint = plugin.mount(VOLUME_NAME, "id").await.unwrap();
+        plugin.remove("removed").await.unwrap();
+        let mounted = plugin.get(VOLUME_NAME).await.unwrap().unwrap();
+
+        let restarted = Plugin::load(temp.path(), Git::init(None).await.unwrap())
+            .await
+            .unwrap();
+
+        restarted
+            .test_in_list(vec![
+                ItemVolume {
+                    name: VOLUME_NAME.into(),
+                    mountpoint: Some(mountpoint.clone()),
+                },
+                ItemVolume {
+                    name: "created".into(),
+                    mountpoint: None,
+                },
+            ])
+            .await;
+        // Timestamps included, the state file keeps them.
+        assert_eq!(restarted.get(VOLUME_NAME).await.unwrap().unwrap(), mounted);
+
+        restarted.unmount(VOLUME_NAME, "id").await.unwrap();
+        assert!(!mountpoint.exists());
+        restarted.remove(VOLUME_NAME).await.unwrap();
+        let restarted = Plugin::load(temp.path(), Git::init(None).await.unwrap())
+            .await
+            .unwrap();
+        restarted.test_in_list_by_names(vec!["created"]).await;
+    }
+
+    #[tokio::test]
+    async fn refetch_mounted_volumes() {
+        let branch_name = "some_branch";
+        let test_repo = TestRepo::new().with_branch(branch_name);
+        let plugin = Plugin::temp()
+            .await
+            .with_temp_volume(
+                VOLUME_NAME,
+                test_repo.create_raw_repo(Some(branch_name.into()), None, Some(true)),
+            )
+            .await
+            .with_temp_volume(
+                "unmounted",
+                test_repo.create_raw_repo(Some(branch_name.into()), None, Some(true)),
+            )
+            .await;
+
+        let mountpoint = plugin.mount(VOLUME_NAME, "id-1").await.unwrap();
+        let unmounted = plugin.mount("unmounted", "id-2").await.unwrap();
+        plugin.unmount("unmounted", "id-2").await.unwrap();
+        test_repo.change(branch_name, "changed value");
+
+        plugin.refetch_mounted(Duration::from_secs(60)).await;
+
+        TestRepo::test_is_changed(&mountpoint, branch_name, "changed value");
+        assert!(!unmounted.exists());
+        let info = plugin.get(VOLUME_NAME).await.unwrap().unwrap();
+        assert_eq!(info.status.commit, Some(test_repo.head(branch_name)));
+    }
+
+    #[tokio::test]
+    async fn refetch_mounted_backs_off_after_failure() {
+        let test_repo = TestRepo::new();
+        let plugin = Plugin::temp()
+            .await
+            .with_temp_volume(
+                VOLUME_NAME,
+                test_repo.create_raw_repo(None, None, Some(true)),
+            )
+            .await;
+        plugin.mount(VOLUME_NAME, "id").await.unwrap();
+        let every = Duration::from_secs(24 * 60 * 60);
+        let failures = async || {
+            let volume = plugin.volumes.try_read(VOLUME_NAME).await.unwrap();
+            (volume.refetch_failures, volume.refetch_retry_at.is_some())
+        };
+
+        std::fs::remove_dir_all(test_repo.path()).unwrap();
+        plugin.refetch_mounted(every).await;
+        assert_eq!(failures().await, (1, true));
+
+        // Still waiting for the retry, the failing remote is not asked again.
+        plugin.refetch_mounted(every).await;
+        assert_eq!(failures().await, (1, true));
+
+        plugin
+            .volumes
+            .try_write(VOLUME_NAME)
+            .await
+            .unwrap()
+            .refetch_retry_at = None;
+        plugin.refetch_mounted(every).await;
+        assert_eq!(failures().await, (2, true));
+    }
+
+    #[tokio::test]
+    async fn volumes_with_equal_options_share_a_clone() {
+        let test_repo = TestRepo::new();
+        let temp = tempfile::tempdir().unwrap();
+        let counter = temp.path().join("clones");
+        let bin = stub_git(
+            temp.path(),
+            &format!(
+                "if [ \"$1\" = clone ]; then echo x >> \"{}\"; fi\nexec git \"$@\"",
+                counter.display()
+            ),
+        );
+        let raw = test_repo.create_raw_repo(None, None, None);
+        let plugin = Plugin::load(temp.path(), Git::stub(&bin))
+            .await
+            .unwrap()
+            .with_share_clones(true)
+            .with_volume("first", raw.clone())
+            .await
+            .with_volume("second", raw)
+            .await;
+
+        let first = plugin.mount("first", "id-1").await.unwrap();
+        let second = plugin.mount("second", "id-2").await.unwrap();
+        assert_eq!(first, second);
+        assert!(first.starts_with(temp.path().join(SHARED_DIR)));
+        assert_eq!(
+            std::fs::read_to_string(&counter).unwrap().lines().count(),
+            1
+        );
+
+        // The users of the clone are known again after a restart.
+        let plugin = Plugin::load(temp.path(), Git::stub(&bin))
+            .await
+            .unwrap()
+            .with_share_clones(true);
+        plugin.unmount("first", "id-1").await.unwrap();
+        assert!(second.exists());
+        plugin.test_path_is("first", None).await;
+
+        plugin.unmount("second", "id-2").await.unwrap();
+        assert!(!second.exists());
+    }
+
+    #[tokio::test]
+    async fn prune_shared_removes_unused_clones() {
+        let test_repo = TestRepo::new();
+        let temp = tempfile::tempdir().unwrap();
+        let plugin = Plugin::load(temp.path(), Git::init(None).await.unwrap())
+            .await
+            .unwrap()
+            .with_share_clones(true)
+            .with_volume("used", test_repo.create_raw_repo(None, None, None))
+            .await;
+        let used = plugin.mount("used", "id-1").await.unwrap();
+        let orphan = temp.path().join(SHARED_DIR).join("orphan");
+        std::fs::create_dir_all(orphan.join(".git")).unwrap();
+
+        let plugin = Plugin::load(temp.path(), Git::init(None).await.unwrap())
+            .await
+            .unwrap();
+        plugin.prune_shared().await;
+        assert!(used.exists());
+        assert!(!orphan.exists());
+    }
+
+    async fn concurrent_mounts(
+        git_body: &str,
+        count: usize,
+    ) -> (Vec<Result<PathBuf, Error>>, usize) {
+        let test_repo = TestRepo::new();
+        let temp = tempfile::tempdir().unwrap();
+        let counter = temp.path().join("clones");
+        let bin = stub_git(
+            temp.path(),
+            &format!(
+                "if [ \"$1\" = clone ]; then echo x >> \"{}\"; sleep 0.3; fi\n{}",
+                counter.display(),
+                git_body
+            ),
+        );
+        let plugin = Plugin::new(temp.path(), Git::stub(&bin))
+            .with_volume(VOLUME_NAME, test_repo.create_raw_repo(None, None, None))
+            .await;
+
+        let mut mounts = tokio::task::JoinSet::new();
+        for i in 0..count {
+            let plugin = plugin.clone();
+            mounts.spawn(async move { plugin.mount(VOLUME_NAME, &format!("id-{i}")).await });
+        }
+        let results = mounts.join_all().await;
+        let clones = std::fs::read_to_string(counter).unwrap().lines().count();
+
+        (results, clones)
+    }
+
+    #[tokio::test]
+    async fn concurrent_mounts_clone_once() {
+        let (results, clones) = concurrent_mounts("exec git \"$@\"", 5).await;
+
+        assert_eq!(clones, 1);
+        let paths: HashSet<PathBuf> = results.into_iter().map(|r| r.unwrap()).collect();
+        assert_eq!(paths.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn clone_concurrency_limit() {
+        let test_repo = TestRepo::new();
+        let temp = tempfile::tempdir().unwrap();
+        let running = temp.path().join("running");
+        let peaks = temp.path().join("peaks");
+        std::fs::create_dir(&running).unwrap();
+        let bin = stub_git(
+            temp.path(),
+            &format!(
+                "if [ \"$1\" != clone ]; then exec git \"$@\"; fi\n\
+                 touch \"{running}/$$\"\n\
+                 ls \"{running}\" | wc -l >> \"{peaks}\"\n\
+                 sleep 0.2\n\
+                 git \"$@\"; code=$?\n\
+                 rm \"{running}/$$\"\n\
+                 exit $code",
+                running = running.display(),
+                peaks = peaks.display(),
+            ),
+        );
+        let mut plugin = Plugin::new(temp.path(), Git::stub(&bin))
+            .with_max_concurrent_clones(NonZeroUsize::new(2).unwrap());
+        for i in 0..5 {
+            plugin = plugin
+                .with_volume(
+                    &format!("volume-{i}"),
+                    test_repo.create_raw_repo(None, None, None),
+                )
+                .await;
+        }
+
+        let mut mounts = tokio::task::JoinSet::new();
+        for i in 0..5 {
+            let plugin = plugin.clone();
+            mounts.spawn(async move { plugin.mount(&format!("volume-{i}"), "id").await });
+        }
+        for result in mounts.join_all().await {
+            result.unwrap();
+        }
+
+        let peaks: Vec<usize> = std::fs::read_to_string(peaks)
+            .unwrap()
+            .lines()
+            .map(|line| line.trim().parse().unwrap())
+            .collect();
+        assert_eq!(peaks.len(), 5);
+        assert!(peaks.iter().all(|&peak| peak <= 2), "{peaks:?}");
+    }
+
+    #[tokio::test]
+    async fn cancelled_mount_kills_clone() {
+        let test_repo = TestRepo::new();
+        let temp = tempfile::tempdir().unwrap();
+        let pid_file = temp.path().join("pid");
+        let bin = stub_git(
+            temp.path(),
+            &format!(
+                "if [ \"$1\" != clone ]; then exec git \"$@\"; fi\n\
+                 for target; do :; done\n\
+                 mkdir -p \"$target\"\n\
+                 echo \"$$ $target\" > \"{}\"\n\
+                 exec sleep 30",
+                pid_file.display(),
+            ),
+        );
+        let plugin = Plugin::new(temp.path(), Git::stub(&bin))
+            .with_volume(VOLUME_NAME, test_repo.create_raw_repo(None, None, None))
+            .await;
+
+        let cancel = CancellationToken::new();
+        let mount = tokio::spawn({
+            let (plugin, cancel) = (plugin.clone(), cancel.clone());
+            async move { plugin.mount_cancellable(VOLUME_NAME, "id", cancel).await }
+        });
+        let started = loop {
+            let started = std::fs::read_to_string(&pid_file).unwrap_or_default();
+            if started.ends_with('\n') {
+                break started;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+        let (pid, target) = started.trim().split_once(' ').unwrap();
+        assert!(Path::new(target).exists());
+        cancel.cancel();
+
+        let error = mount.await.unwrap().unwrap_err();
+        assert!(
+            matches!(error, Error::Git(GitError::Cancelled(_))),
+            "{error:?}"
+        );
+        assert!(
+            !Path::new("/proc").join(pid).exists(),
+            "git is still running"
+        );
+        assert!(!Path::new(target).exists());
+    }
+
+    #[tokio::test]
+    async fn cancelled_mount_kills_hook() {
+        let test_repo = TestRepo::new();
+        let temp = tempfile::tempdir().unwrap();
+        let pid_file = temp.path().join("pid");
+        let plugin = Plugin::new(temp.path(), Git::init(None).await.unwrap())
+            .with_allow_hooks(true)
+            .with_volume(
+                VOLUME_NAME,
+                RawRepo {
+                    post_clone: Some(format!(
+                        "echo $$ > {}.tmp && mv {0}.tmp {0} && exec sleep 30",
+                        pid_file.display()
+                    )),
+                    ..test_repo.create_raw_repo(None, None, None)
+                },
+            )
+            .await;
+
+        let cancel = CancellationToken::new();
+        let mount = tokio::spawn({
+            let (plugin, cancel) = (plugin.clone(), cancel.clone());
+            async move { plugin.mount_cancellable(VOLUME_NAME, "id", cancel).await }
+        });
+        while !pid_file.exists() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let pid = std::fs::read_to_string(&pid_file).unwrap();
+        cancel.cancel();
+
+        let error = mount.await.unwrap().unwrap_err();
+        assert!(
+            matches!(&error, Error::Hook(_, e) if e.is_cancelled()),
+            "{error:?}"
+        );
+        assert!(
+            !Path::new("/proc").join(pid.trim()).exists(),
+            "hook is still running"
+        );
+        let volume = plugin.volumes.try_read(VOLUME_NAME).await.unwrap();
+        assert!(!volume.data_path(&plugin.base_path).exists());
+    }
+
+    #[tokio::test]
+    async fn concurrent_mount_retries_failed_clone() {
+        // The first clone fails, the waiting mount has to clone again instead of failing too.
+        let (results, clones) = concurrent_mounts(
+            "if [ \"$1\" = clone ] && [ ! -e \"$0.failed\" ]; then touch \"$0.failed\"; exit 1; fi\nexec git \"$@\"",
+            2,
+        )
+        .await;
+
+        assert_eq!(clones, 2);
+        assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 1);
+    }
+
+    #[tokio::test]
+    async fn failed_mount_sets_failed_status() {
+        let plugin = Plugin::temp()
             .await
+            .with_temp_volume(
+                VOLUME_NAME,
+                RawRepo::from_url("http://127.0.0.1:1/repo.git"),
+            )
+            .await;
+
+        let error = plugin.mount(VOLUME_NAME, "id").await.unwrap_err();
+
+        let info = plugin.get(VOLUME_NAME).await.unwrap().unwrap();
+        assert_eq!(info.mountpoint, None);
+        assert_eq!(
+            info.status.status,
+            VolumeStatus::Failed {
+                reason: error.to_string()
+            }
+        );
+        let json = serde_json::to_value(&info.status).unwrap();
+        assert_eq!(json["Status"]["Failed"]["reason"], error.to_string());
+    }
+
+    #[tokio::test]
+    async fn cloning_status_during_slow_clone() {
+        let test_repo = TestRepo::new();
+        let temp = tempfile::tempdir().unwrap();
+        let bin = stub_git(
+            temp.path(),
+            "if [ \"$1\" = clone ]; then sleep 0.5; fi\nexec git \"$@\"",
+        );
+        let plugin = Plugin::new(temp.path(), Git::stub(&bin))
+            .with_volume(VOLUME_NAME, test_repo.create_raw_repo(None, None, None))
+            .await;
+
+        let mount = tokio::spawn({
+            let plugin = plugin.clone();
+            async move { plugin.mount(VOLUME_NAME, "id").await }
+        });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let info = plugin.get(VOLUME_NAME).await.unwrap().unwrap();
+        assert_eq!(info.status.status, VolumeStatus::Cloning);
+
+        mount.await.unwrap().unwrap();
+        let info = plugin.get(VOLUME_NAME).await.unwrap().unwrap();
+        assert_eq!(info.status.status, VolumeStatus::Clonned);
+    }
+
+    #[rstest]
+    #[case::unreachable("exec git \"$@\"", "http://127.0.0.1:1/repo.git")]
+    #[case::partial(
+        "for last; do :; done\nmkdir -p \"$last/.git\"\nexit 128",
+        "http://host/repo.git"
+    )]
+    #[tokio::test]
+    async fn failed_clone_leaves_no_directory(#[case] git_body: &str, #[case] url: &str) {
+        let temp = tempfile::tempdir().unwrap();
+        let bin = stub_git(temp.path(), git_body);
+        let base_path = temp.path().join("volumes");
+        std::fs::create_dir(&base_path).unwrap();
+        let plugin = Plugin::new(&base_path, Git::stub(&bin))
+            .with_volume(
+                VOLUME_NAME,
+                RawRepo {
+                    url: Some(url.into()),
+                    ..Default::default()
+                },
+            )
+            .await;
+
+        let error = plugin.mount(VOLUME_NAME, "id").await.unwrap_err();
+
+        assert!(matches!(error, Error::Git(_)), "{error:?}");
+        assert_eq!(std::fs::read_dir(&base_path).unwrap().count(), 0);
+        plugin
             .test_stub_path_is(None)
             .await
             .test_get_stub_volume(VolumeInfo {
-                status: VolumeStatus::Created.into(),
                 mountpoint: None,
+                status: VolumeStatus::Failed {
+                    reason: error.to_string(),
+                }
+                .into(),
             })
             .await;
-    }
+    }
+
+    #[tokio::test]
+    async fn mount_clone_failure_on_bad_url() {
+        let plugin = Plugin::stub().await.with_volume(
+            VOLUME_NAME,
+            RawRepo {
+                url: Some("http://host/path-to-git-repo".into()),
+                ..Default::default()
+            },
+        );
+
+        let result = plugin.await.mount(VOLUME_NAME, "id").await;
+        assert!(
+            result.is_err(),
+            " Mounting a non-existent repository should result in an error."
+        );
+    }
+
+    #[tokio::test]
+    async fn unmount_nonexistent_ok() {
+        let plugin = Plugin::stub().await;
+
+        let result = plugin.unmount("name", "id").await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn remount_expands_compressed_volume() {
+        let (test_repo, plugin) = Plugin::temp()
+            .await
+            .configure(|plugin| plugin.with_compress_cleared(true))
+            .with_stub_test_repo()
+            .await;
+
+        let mountpoint = plugin.mount(VOLUME_NAME, "id-1").await.unwrap();
+        plugin.unmount(VOLUME_NAME, "id-1").await.unwrap();
+
+        let archive = archive::archive_path(&mountpoint);
+        assert!(!mountpoint.exists());
+        assert!(archive.exists());
+
+        // Without the upstream repository a fresh clone would fail.
+        drop(test_repo);
+
+        let remounted = plugin.mount(VOLUME_NAME, "id-2").await.unwrap();
+        assert_eq!(remounted, mountpoint);
+        assert!(mountpoint.join("branch-master").exists());
+        assert!(!archive.exists());
+
+        plugin.unmount(VOLUME_NAME, "id-2").await.unwrap();
+        plugin.remove(VOLUME_NAME).await.unwrap();
+        assert!(!mountpoint.exists());
+    }
+
+    #[tokio::test]
+    async fn readonly_mount() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let test_repo = TestRepo::new();
+        let plugin = Plugin::temp()
+            .await
+            .with_temp_volume(
+                VOLUME_NAME,
+                RawRepo {
+                    readonly: Some(true),
+                    ..test_repo.create_raw_repo(None, None, Some(true))
+                },
+            )
+            .await;
 
-    #[tokio::test]
-    async fn create_duplicate_name_error() {
-        let plugin = Plugin::stub().await.with_stub_volume().await;
+        let mountpoint = plugin.mount(VOLUME_NAME, "id-1").await.unwrap();
+        // Refetching has to lift the permissions and apply them again.
+        plugin.mount(VOLUME_NAME, "id-2").await.unwrap();
+        plugin.refresh(VOLUME_NAME).await.unwrap();
 
-        let second_creating = plugin.create(VOLUME_NAME, Some(RawRepo::stub())).await;
-        assert!(
-            second_creating.is_err(),
-            "Recreating the volume (with the same name) should result in an error."
-        );
+        for path in [mountpoint.clone(), mountpoint.join("branch-master")] {
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o222, 0, "{path:?} is writable");
+        }
 
-        let error = second_creating.unwrap_err();
-        assert!(matches!(
-            error,
-            Error::Volumes(VolumesError::AlreadyExists(_))
-        ));
+        plugin.unmount(VOLUME_NAME, "id-1").await.unwrap();
+        plugin.unmount(VOLUME_NAME, "id-2").await.unwrap();
+        assert!(!mountpoint.exists());
 
-        plugin.test_in_list_by_names(vec![VOLUME_NAME]).await;
+        plugin.mount(VOLUME_NAME, "id-3").await.unwrap();
+        plugin.unmount(VOLUME_NAME, "id-3").await.unwrap();
+        plugin.remove(VOLUME_NAME).await.unwrap();
+        assert!(!mountpoint.exists());
     }
 
     #[rstest]
-    #[case("", Some(RawRepo::stub()))]
-    #[case("     ", Some(RawRepo::stub()))]
-    #[case(VOLUME_NAME, None)]
-    #[case(VOLUME_NAME, Some(RawRepo::default()))]
-    #[case(VOLUME_NAME, Some(RawRepo { branch: Some("some_branch".into()), tag: Some("some_tag".into()), ..RawRepo::stub()}))]
-    #[case(VOLUME_NAME, Some(RawRepo { url: Some("ftp://host/path-to-git-repo".into()), ..RawRepo::default()}))]
+    #[case(false, 0o755)]
+    #[case(true, 0o555)]
     #[tokio::test]
-    async fn create_invalid_params_error(
-        #[case] volume_name: &str,
-        #[case] raw_repo: Option<RawRepo>,
-    ) {
-        let plugin = Plugin::stub().await;
+    async fn volume_mode(#[case] readonly: bool, #[case] expected: u32) {
+        use std::os::unix::fs::PermissionsExt;
 
-        let result = plugin.create(volume_name, raw_repo.clone()).await;
-        assert!(
-            result.is_err(),
-            "Creating a volume with incorrect parameters should result in an error. name={:?}; options={:?}",
-            volume_name,
-            raw_repo
-        );
+        let test_repo = TestRepo::new();
+        let plugin = Plugin::temp()
+            .await
+            .configure(|plugin| plugin.with_volume_mode(Some(0o755)))
+            .with_temp_volume(
+                VOLUME_NAME,
+                RawRepo {
+                    readonly: Some(readonly),
+                    ..test_repo.create_raw_repo(None, None, Some(true))
+                },
+            )
+            .await;
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
 
-        let error = result.unwrap_err();
-        assert!(matches!(error, Error::Volumes(_)));
-        plugin.test_is_empty_list().await;
+        let mountpoint = plugin.mount(VOLUME_NAME, "id-1").await.unwrap();
+        assert_eq!(mode(&mountpoint), expected);
+        assert_eq!(mode(&mountpoint.join("branch-master")), expected & !0o111);
+
+        plugin.refresh(VOLUME_NAME).await.unwrap();
+        assert_eq!(mode(&mountpoint), expected);
+
+        plugin.unmount(VOLUME_NAME, "id-1").await.unwrap();
+        plugin.remove(VOLUME_NAME).await.unwrap();
     }
 
     #[tokio::test]
-    async fn list_multiple_volumes() {
-        Plugin::stub()
-            .await
-            .with_stub_volume()
+    async fn storage_classes() {
+        let test_repo = TestRepo::new();
+        let (fast, bulk) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let classes = HashMap::from([
+            ("fast".to_string(), fast.path().to_path_buf()),
+            ("bulk".to_string(), bulk.path().to_path_buf()),
+        ]);
+        let plugin = Plugin::temp()
             .await
-            .with_volume("other_volume", RawRepo::stub())
+            .configure(|plugin| plugin.with_storage_classes(classes));
+        let raw_repo = |class: Option<&str>| RawRepo {
+            storage_class: class.map(str::to_string),
+            ..test_repo.create_raw_repo(None, None, None)
+        };
+
+        let error = plugin
+            .create("unknown", Some(raw_repo(Some("slow"))))
             .await
-            .test_in_list_by_names(vec![VOLUME_NAME, "other_volume"])
-            .await;
+            .unwrap_err();
+        assert!(matches!(error, Error::UnknownStorageClass(ref class) if class == "slow"));
+        assert_eq!(error.code(), "INVALID_OPTIONS");
+
+        for (name, class, base) in [
+            ("on-fast", Some("fast"), fast.path()),
+            ("on-bulk", Some("bulk"), bulk.path()),
+            ("default", None, plugin.base_path.as_path()),
+        ] {
+            plugin.create(name, Some(raw_repo(class))).await.unwrap();
+            let mountpoint = plugin.mount(name, "id").await.unwrap();
+            assert!(mountpoint.starts_with(base), "{mountpoint:?}");
+            TestRepo::test_is_branch(&mountpoint, "master");
+        }
+
+        for name in ["on-fast", "on-bulk"] {
+            plugin.unmount(name, "id").await.unwrap();
+            plugin.remove(name).await.unwrap();
+        }
+        assert_eq!(std::fs::read_dir(fast.path()).unwrap().count(), 0);
+        assert_eq!(std::fs::read_dir(bulk.path()).unwrap().count(), 0);
     }
 
     #[tokio::test]
-    async fn path_after_mount_returns_some() {
-        let (_g, plugin) = Plugin::temp().await.with_stub_test_repo().await;
-
-        let mountpoint = plugin.mount(VOLUME_NAME, "id-123").await.unwrap();
+    async fn remove_after_storage_class_unconfigured() {
+        let test_repo = TestRepo::new();
+        let (temp, fast) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let classes = HashMap::from([("fast".to_string(), fast.path().to_path_buf())]);
+        let plugin = Plugin::load(temp.path(), Git::init(None).await.unwrap())
+            .await
+            .unwrap()
+            .with_storage_classes(classes);
+        let raw_repo = RawRepo {
+            storage_class: Some("fast".into()),
+            ..test_repo.create_raw_repo(None, None, None)
+        };
+        plugin.create(VOLUME_NAME, Some(raw_repo)).await.unwrap();
+        plugin.mount(VOLUME_NAME, "id").await.unwrap();
+        plugin.unmount(VOLUME_NAME, "id").await.unwrap();
 
-        plugin.test_stub_path_is(Some(mountpoint)).await;
+        // Restarted without the class, the base saved with the volume still leads to its clone.
+        let plugin = Plugin::load(temp.path(), Git::init(None).await.unwrap())
+            .await
+            .unwrap();
+        plugin.remove(VOLUME_NAME).await.unwrap();
+        assert_eq!(std::fs::read_dir(fast.path()).unwrap().count(), 0);
     }
 
     #[tokio::test]
-    async fn get_created_unmounted_status() {
-        let (_g, plugin) = Plugin::temp().await.with_stub_test_repo().await;
+    async fn lifecycle_events() {
+        use crate::services::events::test::read_events;
 
-        let created = plugin.get(VOLUME_NAME).await.unwrap();
-        assert_eq!(
-            created.status,
-            Status {
-                status: VolumeStatus::Created
-            }
-        );
+        let test_repo = TestRepo::new();
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("events");
+        let plugin = Plugin::temp()
+            .await
+            .configure(|plugin| plugin.with_events(Events::open(&path)))
+            .with_temp_volume(VOLUME_NAME, test_repo.create_raw_repo(None, None, None))
+            .await
+            .with_temp_volume("missing", RawRepo::from_url("file:///nonexistent/repo.git"))
+            .await;
 
-        plugin.mount(VOLUME_NAME, "id-123").await.unwrap();
-        plugin.unmount(VOLUME_NAME, "id-123").await.unwrap();
+        plugin.mount(VOLUME_NAME, "id").await.unwrap();
+        plugin.mount("missing", "id").await.unwrap_err();
+        plugin.unmount(VOLUME_NAME, "id").await.unwrap();
 
-        let cleared = plugin.get(VOLUME_NAME).await.unwrap();
+        let events = read_events(&path, 5).await;
+        let summary: Vec<(String, &str)> = events
+            .iter()
+            .map(|event| {
+                let kind = serde_json::to_value(event.event).unwrap();
+                (kind.as_str().unwrap().to_string(), event.name.as_str())
+            })
+            .collect();
         assert_eq!(
-            cleared.status,
-            Status {
-                status: VolumeStatus::Cleared
-            }
+            summary,
+            [
+                ("created".to_string(), VOLUME_NAME),
+                ("created".to_string(), "missing"),
+                ("mounted".to_string(), VOLUME_NAME),
+                ("mount_failed".to_string(), "missing"),
+                ("unmounted".to_string(), VOLUME_NAME),
+            ]
         );
+        assert_eq!(events[2].commit, Some(test_repo.head("master")));
+        assert_eq!(events[2].id.as_deref(), Some("id"));
+        assert!(events[3].error.is_some());
     }
 
     #[tokio::test]
-    async fn get_after_mount_status_clonned() {
-        let (_g, plugin) = Plugin::temp().await.with_stub_test_repo().await;
-
-        let mountpoint = plugin.mount(VOLUME_NAME, "id-123").await.unwrap();
-
-        assert!(mountpoint.exists());
-        plugin
-            .test_get_stub_volume(VolumeInfo {
-                mountpoint: Some(mountpoint),
-                status: Status {
-                    status: VolumeStatus::Clonned,
-                },
-            })
-            .await;
+    async fn mount_marks_volume_dir() {
+        let (_test_repo, plugin) = Plugin::temp().await.with_stub_test_repo().await;
+
+        let path = plugin.mount(VOLUME_NAME, "id").await.unwrap();
+
+        let volume = plugin.volumes.read(VOLUME_NAME).await.unwrap();
+        let content = std::fs::read(volume.dir(&plugin.base_path).join(store::MARKER_FILE));
+        let marker: store::Marker = serde_json::from_slice(&content.unwrap()).unwrap();
+        assert_eq!(marker.name, VOLUME_NAME);
+        assert_eq!(marker.hash, volume.hash());
+        assert_eq!(marker.created_at, timestamp::rfc3339(volume.created_at));
+        assert!(!path.join(".git").exists());
+        assert!(!path.join(store::MARKER_FILE).exists());
     }
 
+    #[rstest]
+    #[case::foreign(false)]
+    #[case::interrupted_clone(true)]
     #[tokio::test]
-    async fn remove_nonexistent_by_empty_ok() {
-        let plugin = Plugin::stub().await;
-        let result = plugin.remove("other_volume").await;
-        assert!(result.is_ok());
+    async fn mount_over_existing_path(#[case] marked: bool) {
+        let (_test_repo, plugin) = Plugin::temp().await.with_stub_test_repo().await;
+        let path = plugin
+            .volumes
+            .read(VOLUME_NAME)
+            .await
+            .unwrap()
+            .data_path(&plugin.base_path);
+        std::fs::create_dir_all(&path).unwrap();
+        std::fs::write(path.join("keep"), "").unwrap();
+        if marked {
+            let volume = plugin.volumes.read(VOLUME_NAME).await.unwrap();
+            store::mark(&plugin.base_path, &volume).await.unwrap();
+        }
 
-        plugin.test_is_empty_list().await;
+        let mounted = plugin.mount(VOLUME_NAME, "id").await;
+
+        if marked {
+            assert_eq!(mounted.unwrap(), path);
+            assert!(!path.join("keep").exists());
+        } else {
+            let error = mounted.unwrap_err();
+            assert!(matches!(&error, Error::ForeignPath(foreign) if *foreign == path));
+            assert_eq!(error.code(), "FOREIGN_PATH");
+            assert!(path.join("keep").exists());
+        }
     }
 
     #[tokio::test]
-    async fn remove_nonexistent_with_other_volumes_ok() {
-        let plugin = Plugin::stub().await.with_stub_volume().await;
-
-        let result = plugin.remove("other_volume").await;
-        assert!(result.is_ok());
-
-        plugin.test_in_list_by_names(vec![VOLUME_NAME]).await;
+    async fn load_marks_existing_volume_dirs() {
+        let test_repo = TestRepo::new();
+        let temp = tempfile::tempdir().unwrap();
+        let git = Git::init(None).await.unwrap();
+        let plugin = Plugin::load(temp.path(), git).await.unwrap();
+        plugin
+            .create(
+                VOLUME_NAME,
+                Some(test_repo.create_raw_repo(None, None, None)),
+            )
+            .await
+            .unwrap();
+        // Left by a clone interrupted before the plugin wrote markers.
+        let volume = plugin.volumes.read(VOLUME_NAME).await.unwrap();
+        let path = volume.data_path(temp.path());
+        std::fs::create_dir_all(&path).unwrap();
+        std::fs::write(path.join("keep"), "").unwrap();
+        assert!(!volume.dir(temp.path()).join(store::MARKER_FILE).exists());
+
+        let git = Git::init(None).await.unwrap();
+        let plugin = Plugin::load(temp.path(), git).await.unwrap();
+
+        assert!(volume.dir(temp.path()).join(store::MARKER_FILE).exists());
+        assert_eq!(plugin.mount(VOLUME_NAME, "id").await.unwrap(), path);
+        assert!(!path.join("keep").exists());
     }
 
     #[tokio::test]
-    async fn remove_existing_unmounted_ok() {
-        let plugin = Plugin::stub().await.with_stub_volume().await;
-
-        let result = plugin.remove(VOLUME_NAME).await;
-        assert!(result.is_ok());
-
+    async fn host_policy_applies_to_existing_volumes() {
+        let policy = || HostPolicy::new(vec!["example.com".into()], vec![]);
+        let (test_repo, plugin) = Plugin::temp().await.with_stub_test_repo().await;
+        let path = plugin.mount(VOLUME_NAME, "id").await.unwrap();
         plugin
-            .test_is_empty_list()
+            .create("other", Some(test_repo.create_raw_repo(None, None, None)))
             .await
-            .test_stub_path_is(None)
-            .await;
+            .unwrap();
+        let plugin = plugin.configure(|plugin| plugin.with_host_policy(policy()));
+
+        let error = plugin.mount("other", "id").await.unwrap_err();
+        assert_eq!(error.code(), "HOST_NOT_ALLOWED");
+        let volume = plugin.volumes.try_read("other").await.unwrap();
+        assert!(!volume.data_path(&plugin.base_path).exists());
+        let repo = plugin
+            .volumes
+            .try_read(VOLUME_NAME)
+            .await
+            .unwrap()
+            .repo
+            .clone();
+        let error = plugin.refetch(&path, &repo).await.unwrap_err();
+        assert_eq!(error.code(), "HOST_NOT_ALLOWED");
     }
 
     #[tokio::test]
-    async fn remove_existing_mounted_ok() {
-        let (_g, plugin) = Plugin::temp().await.with_stub_test_repo().await;
+    async fn mount_after_restart_needs_credentials_again() {
+        let test_repo = TestRepo::new();
+        let raw = || RawRepo {
+            token: Some("s3cr3t".into()),
+            ..test_repo.create_raw_repo(None, None, None)
+        };
+        let temp = tempfile::tempdir().unwrap();
+        Plugin::load(temp.path(), Git::init(None).await.unwrap())
+            .await
+            .unwrap()
+            .create(VOLUME_NAME, Some(raw()))
+            .await
+            .unwrap();
 
-        let mountpoint = plugin.mount(VOLUME_NAME, "id").await.unwrap();
-        let result = plugin.remove(VOLUME_NAME).await;
-        assert!(result.is_ok());
+        let plugin = Plugin::load(temp.path(), Git::init(None).await.unwrap())
+            .await
+            .unwrap();
+        let error = plugin.mount(VOLUME_NAME, "id").await.unwrap_err();
+        assert_eq!(error.code(), "AUTH_LOST");
 
-        plugin.test_is_empty_list().await;
-        assert!(!mountpoint.exists());
+        plugin.create(VOLUME_NAME, Some(raw())).await.unwrap();
+        plugin.mount(VOLUME_NAME, "id").await.unwrap();
     }
 
     #[tokio::test]
-    async fn mount_first_time_clones_repo() {
-        let (test_repo, plugin) = Plugin::temp().await.with_stub_test_repo().await;
+    async fn mount_rejects_large_clone() {
+        let test_repo = TestRepo::new().with_files(&[("assets/blob", &"x".repeat(4096))]);
+        let plugin = Plugin::temp()
+            .await
+            .with_temp_volume(VOLUME_NAME, test_repo.create_raw_repo(None, None, None))
+            .await
+            .configure(|plugin| plugin.with_max_clone_bytes(Some(1024)));
 
-        let mountpoint = plugin.mount(VOLUME_NAME, "id").await.unwrap();
+        let error = plugin.mount(VOLUME_NAME, "id").await.unwrap_err();
 
-        TestRepo::test_is_not_git(&mountpoint);
-        test_repo.test_is_default_branch(&mountpoint);
+        assert!(
+            matches!(&error, Error::CloneTooLarge { name, size, limit: 1024 } if name == VOLUME_NAME && *size >= 4096),
+            "{error:?}"
+        );
+        assert_eq!(error.code(), "CLONE_TOO_LARGE");
+        let volume = plugin.volumes.read(VOLUME_NAME).await.unwrap();
+        assert!(!volume.data_path(&plugin.base_path).exists());
+        assert!(matches!(volume.status, VolumeStatus::Failed { .. }));
     }
 
     #[tokio::test]
-    async fn mount_when_already_mounted_no_clone() {
-        let (_g, plugin) = Plugin::temp().await.with_stub_test_repo().await;
+    async fn mount_checks_free_space() {
+        let (_test_repo, plugin) = Plugin::temp().await.with_stub_test_repo().await;
+        let plugin = plugin.configure(|plugin| plugin.with_min_free_bytes(u64::MAX));
 
-        let first_mountpoint = plugin.mount(VOLUME_NAME, "id-1").await.unwrap();
-        let second_mountpoint = plugin.mount(VOLUME_NAME, "id-2").await.unwrap();
+        let error = plugin.mount(VOLUME_NAME, "id").await.unwrap_err();
 
-        assert_eq!(first_mountpoint, second_mountpoint);
+        assert!(
+            matches!(
+                error,
+                Error::InsufficientSpace {
+                    required: u64::MAX,
+                    ..
+                }
+            ),
+            "{error:?}"
+        );
+        assert_eq!(error.code(), "DISK_FULL");
+        assert!(
+            plugin
+                .metrics()
+                .await
+                .contains("gitvol_clone_total{result=\"failure\"} 0")
+        );
+        let volume = plugin.volumes.read(VOLUME_NAME).await.unwrap();
+        assert!(!volume.data_path(&plugin.base_path).exists());
     }
 
     #[tokio::test]
-    async fn mount_with_branch() {
-        let test_repo = TestRepo::new().with_branch("develop");
-        let plugin = Plugin::temp()
-            .await
-            .with_temp_volume(
-                VOLUME_NAME,
-                test_repo.create_raw_repo(Some("develop".into()), None, None),
+    async fn mount_records_clone_metrics() {
+        let (_test_repo, plugin) = Plugin::temp().await.with_stub_test_repo().await;
+        plugin
+            .create(
+                "missing",
+                Some(RawRepo::from_url("http://127.0.0.1:1/repo.git")),
             )
-            .await;
+            .await
+            .unwrap();
 
-        let mountpoint = plugin.mount(VOLUME_NAME, "id").await.unwrap();
-        TestRepo::test_is_branch(&mountpoint, "develop");
-    }
+        plugin.mount(VOLUME_NAME, "id-1").await.unwrap();
+        plugin.mount(VOLUME_NAME, "id-2").await.unwrap();
+        plugin.mount("missing", "id-3").await.unwrap_err();
 
-    #[tokio::test]
-    async fn mount_with_tag() {
-        let test_repo = TestRepo::new().with_tag("v1");
-        let plugin = Plugin::temp()
-            .await
-            .with_temp_volume(
-                VOLUME_NAME,
-                test_repo.create_raw_repo(None, Some("v1".into()), None),
-            )
-            .await;
+        let metrics = plugin.metrics().await;
+        assert!(
+            metrics.contains("gitvol_clone_total{result=\"success\"} 1\n"),
+            "{metrics}"
+        );
+        assert!(
+            metrics.contains("gitvol_clone_total{result=\"failure\"} 1\n"),
+            "{metrics}"
+        );
+        assert!(
+            metrics.contains("gitvol_clone_duration_seconds_count 2\n"),
+            "{metrics}"
+        );
+        assert!(metrics.contains("gitvol_volumes_active 1\n"), "{metrics}");
 
-        let mountpoint = plugin.mount(VOLUME_NAME, "id").await.unwrap();
-        TestRepo::test_is_tag(&mountpoint, "v1");
+        plugin.unmount(VOLUME_NAME, "id-1").await.unwrap();
+        plugin.unmount(VOLUME_NAME, "id-2").await.unwrap();
+        assert!(plugin.metrics().await.contains("gitvol_volumes_active 0\n"));
     }
 
     #[tokio::test]
-    async fn mount_with_refetch() {
-        let branch_name = "some_branch";
-        let test_repo = TestRepo::new().with_branch(branch_name);
+    async fn isolated_mounts() {
+        let test_repo = TestRepo::new();
         let plugin = Plugin::temp()
             .await
             .with_temp_volume(
                 VOLUME_NAME,
-                test_repo.create_raw_repo(Some(branch_name.into()), None, Some("true".into())),
+                RawRepo {
+                    isolated: Some(true),
+                    ..test_repo.create_raw_repo(None, None, None)
+                },
             )
             .await;
 
-        let mountpoint = plugin.mount(VOLUME_NAME, "id-1").await.unwrap();
-        TestRepo::test_is_git(&mountpoint);
-        TestRepo::test_is_branch(&mountpoint, branch_name);
-
-        test_repo.change(branch_name, "changed value");
-
-        plugin.mount(VOLUME_NAME, "id-2").await.unwrap();
-        TestRepo::test_is_changed(&mountpoint, branch_name, "changed value");
-    }
-
-    #[tokio::test]
-    async fn mount_clone_failure_on_bad_url() {
-        let plugin = Plugin::stub().await.with_volume(
-            VOLUME_NAME,
-            RawRepo {
-                url: Some("http://host/path-to-git-repo".into()),
-                ..Default::default()
-            },
-        );
+        let first = plugin.mount(VOLUME_NAME, "id-1").await.unwrap();
+        let second = plugin.mount(VOLUME_NAME, "id-2").await.unwrap();
+        assert_ne!(first, second);
+        test_repo.test_is_default_branch(&first);
+        test_repo.test_is_default_branch(&second);
 
-        let result = plugin.await.mount(VOLUME_NAME, "id").await;
-        assert!(
-            result.is_err(),
-            " Mounting a non-existent repository should result in an error."
-        );
-    }
+        std::fs::write(first.join("scratch"), "").unwrap();
+        assert!(!second.join("scratch").exists());
+        assert_eq!(plugin.mount(VOLUME_NAME, "id-1").await.unwrap(), first);
 
-    #[tokio::test]
-    async fn unmount_nonexistent_ok() {
-        let plugin = Plugin::stub().await;
+        plugin.unmount(VOLUME_NAME, "id-1").await.unwrap();
+        assert!(!first.exists());
+        test_repo.test_is_default_branch(&second);
+        let info = plugin.get(VOLUME_NAME).await.unwrap().unwrap();
+        assert_eq!(info.status.status, VolumeStatus::Clonned);
 
-        let result = plugin.unmount("name", "id").await;
-        assert!(result.is_ok());
+        plugin.unmount(VOLUME_NAME, "id-2").await.unwrap();
+        assert!(!second.exists());
+        assert!(!second.parent().unwrap().exists());
+        let info = plugin.get(VOLUME_NAME).await.unwrap().unwrap();
+        assert_eq!(info.mountpoint, None);
+        assert_eq!(info.status.status, VolumeStatus::Cleared);
     }
 
     #[tokio::test]
     async fn unmount_with_multiple_containers_keeps_dir() {
-        let (_g, plugin) = Plugin::temp().await.with_stub_test_repo().await;
+        let (test_repo, plugin) = Plugin::temp().await.with_stub_test_repo().await;
 
         let mountpoint = plugin.mount(VOLUME_NAME, "id-1").await.unwrap();
         plugin.mount(VOLUME_NAME, "id-2").await.unwrap();
@@ -645,7 +3689,16 @@ mod test {
             .await
             .test_get_stub_volume(VolumeInfo {
                 mountpoint: Some(mountpoint.clone()),
-                status: VolumeStatus::Clonned.into(),
+                status: Status {
+                    status: VolumeStatus::Clonned,
+                    commit: Some(test_repo.head("master")),
+                    branch: Some("master".into()),
+                    size_bytes: Some(disk::dir_size(&mountpoint).await.unwrap()),
+                    available: Some(true),
+                    containers: 1,
+                    created_at: None,
+                    updated_at: None,
+                },
             })
             .await;
         assert!(mountpoint.exists());
@@ -687,10 +3740,39 @@ mod test {
         assert!(mountpoint.exists());
     }
 
+    #[tokio::test]
+    async fn timestamps() {
+        let (_test_repo, plugin) = Plugin::temp().await.with_stub_test_repo().await;
+        let times = || async {
+            let volume = plugin.volumes.try_read(VOLUME_NAME).await.unwrap();
+            (volume.created_at, volume.updated_at)
+        };
+
+        let (created_at, updated_at) = times().await;
+        assert_eq!(created_at, updated_at);
+
+        plugin.mount(VOLUME_NAME, "id-1").await.unwrap();
+        let (mounted_created_at, mounted_at) = times().await;
+        assert_eq!(mounted_created_at, created_at);
+        assert!(mounted_at > updated_at);
+
+        plugin.mount(VOLUME_NAME, "id-2").await.unwrap();
+        plugin.unmount(VOLUME_NAME, "id-1").await.unwrap();
+        let (unmounted_created_at, unmounted_at) = times().await;
+        assert_eq!(unmounted_created_at, created_at);
+        assert!(unmounted_at > mounted_at);
+
+        let info = plugin.get(VOLUME_NAME).await.unwrap().unwrap();
+        assert_eq!(info.status.created_at, Some(timestamp::rfc3339(created_at)));
+        let json = serde_json::to_value(&info.status).unwrap();
+        assert_eq!(json["CreatedAt"], timestamp::rfc3339(created_at));
+        assert_eq!(json["UpdatedAt"], timestamp::rfc3339(unmounted_at));
+    }
+
     async fn full_check<P: Deref<Target = Plugin>>(
         plugin: &P,
         mountpoint: Option<PathBuf>,
-        status: VolumeStatus,
+        status: impl Into<Status>,
     ) {
         plugin
             .test_in_list(vec![ItemVolume {
@@ -709,11 +3791,21 @@ mod test {
 
     #[tokio::test]
     async fn happy_flow_create_mount_get_path_unmount_remove() {
-        let (_g, plugin) = Plugin::temp().await.with_stub_test_repo().await;
+        let (test_repo, plugin) = Plugin::temp().await.with_stub_test_repo().await;
         full_check(&plugin, None, VolumeStatus::Created).await;
 
         let mountpoint = plugin.mount(VOLUME_NAME, "id").await.unwrap();
-        full_check(&plugin, Some(mountpoint.clone()), VolumeStatus::Clonned).await;
+        let clonned = Status {
+            status: VolumeStatus::Clonned,
+            commit: Some(test_repo.head("master")),
+            branch: Some("master".into()),
+            size_bytes: Some(disk::dir_size(&mountpoint).await.unwrap()),
+            available: Some(true),
+            containers: 1,
+            created_at: None,
+            updated_at: None,
+        };
+        full_check(&plugin, Some(mountpoint.clone()), clonned).await;
         assert!(mountpoint.exists());
 
         plugin.unmount(VOLUME_NAME, "id").await.unwrap();
@@ -0,0 +1,167 @@
+use std::{
+    fs::{self, Permissions},
+    future::Future,
+    io,
+    os::unix::fs::{PermissionsExt, chown},
+    path::Path,
+};
+
+use axum::{Router, serve};
+use tokio::{
+    net::{TcpListener, UnixListener},
+    signal::unix::{SignalKind, signal},
+};
+
+use crate::settings::Listen;
+
+pub enum Listener {
+    Unix(UnixListener),
+    Tcp(TcpListener),
+}
+
+impl Listener {
+    pub async fn bind(listen: &Listen) -> io::Result<Self> {
+        Ok(match listen {
+            Listen::Unix(socket) => Self::Unix(UnixListener::bind(socket)?),
+            Listen::Tcp(addr) => Self::Tcp(TcpListener::bind(addr).await?),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<String> {
+        Ok(match self {
+            Self::Unix(listener) => format!("{:?}", listener.local_addr()?),
+            Self::Tcp(listener) => format!("tcp://{}", listener.local_addr()?),
+        })
+    }
+
+    /// Serves until `shutdown` resolves, then stops accepting connections and waits for the
+    /// in-flight requests, so a mount is not aborted halfway through a clone.
+    pub async fn serve<F>(self, app: Router, shutdown: F) -> io::Result<()>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        match self {
+            Self::Unix(listener) => serve(listener, app).with_graceful_shutdown(shutdown).await,
+            Self::Tcp(listener) => serve(listener, app).with_graceful_shutdown(shutdown).await,
+        }
+    }
+}
+
+/// Applies the configured mode and group to a bound unix socket, so the docker daemon can connect.
+pub fn set_socket_permissions(
+    socket: &Path,
+    mode: Option<u32>,
+    gid: Option<u32>,
+) -> io::Result<()> {
+    if let Some(gid) = gid {
+        chown(socket, None, Some(gid))?;
+    }
+    if let Some(mode) = mode {
+        fs::set_permissions(socket, Permissions::from_mode(mode))?;
+    }
+    Ok(())
+}
+
+/// Resolves on the first SIGTERM or SIGINT.
+pub async fn shutdown_signal() -> io::Result<()> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    tokio::select! {
+        _ = terminate.recv() => println!("Received SIGTERM, shutting down."),
+        _ = interrupt.recv() => println!("Received SIGINT, shutting down."),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{driver::Driver, plugin::Plugin};
+    use axum::routing::post;
+    use std::{future::pending, net::SocketAddr, time::Duration};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+        sync::oneshot,
+        time::sleep,
+    };
+
+    async fn post_request(addr: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = format!(
+            "POST {path} HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    async fn bind_tcp() -> (Listener, SocketAddr) {
+        let listener = Listener::bind(&Listen::Tcp("127.0.0.1:0".into()))
+            .await
+            .unwrap();
+        let Listener::Tcp(tcp) = &listener else {
+            unreachable!()
+        };
+        let addr = tcp.local_addr().unwrap();
+        (listener, addr)
+    }
+
+    #[tokio::test]
+    async fn serve_over_tcp() {
+        let (listener, addr) = bind_tcp().await;
+        tokio::spawn(listener.serve(Plugin::stub().await.into_router(), pending()));
+
+        let response = post_request(addr, "/Plugin.Activate").await;
+
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        assert!(response.contains("VolumeDriver"), "{response}");
+    }
+
+    #[tokio::test]
+    async fn socket_permissions() {
+        use std::os::unix::fs::MetadataExt;
+
+        let temp = tempfile::tempdir().unwrap();
+        let socket = temp.path().join("plugin.sock");
+        let _listener = Listener::bind(&Listen::Unix(socket.clone())).await.unwrap();
+
+        // Any user may chown to its own group, root is not needed.
+        let file = temp.path().join("file");
+        fs::write(&file, "").unwrap();
+        let gid = fs::metadata(&file).unwrap().gid();
+
+        set_socket_permissions(&socket, Some(0o660), Some(gid)).unwrap();
+
+        let metadata = fs::metadata(&socket).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o660);
+        assert_eq!(metadata.gid(), gid);
+    }
+
+    #[tokio::test]
+    async fn shutdown_drains_in_flight_requests() {
+        let (listener, addr) = bind_tcp().await;
+        let app = Router::new().route(
+            "/slow",
+            post(|| async {
+                sleep(Duration::from_millis(300)).await;
+                "done"
+            }),
+        );
+        let (shutdown, shutdown_rx) = oneshot::channel::<()>();
+        let server = tokio::spawn(listener.serve(app, async {
+            _ = shutdown_rx.await;
+        }));
+
+        let request = tokio::spawn(post_request(addr, "/slow"));
+        sleep(Duration::from_millis(100)).await;
+        shutdown.send(()).unwrap();
+
+        let response = request.await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        assert!(response.ends_with("done"), "{response}");
+        server.await.unwrap().unwrap();
+        assert!(TcpStream::connect(addr).await.is_err());
+    }
+}
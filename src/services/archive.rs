@@ -0,0 +1,83 @@
+use std::path::{Path, PathBuf};
+
+use tokio::fs;
+
+use crate::domains::cmd::{Cmd, Error as CmdError};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Cmd(#[from] CmdError),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+pub fn archive_path(dir: &Path) -> PathBuf {
+    let mut path = dir.as_os_str().to_owned();
+    path.push(".tar.gz");
+    PathBuf::from(path)
+}
+
+/// Packs `dir` into `<dir>.tar.gz` next to it and removes the expanded tree.
+pub async fn compress(dir: &Path) -> Result<PathBuf, Error> {
+    let archive = archive_path(dir);
+    println!("Compressing {:?} to {:?}", dir, archive);
+
+    Cmd::new("tar")
+        .arg("-czf")
+        .arg(archive.to_str().unwrap_or_default())
+        .args(["-C", dir.to_str().unwrap_or_default(), "."])
+        .exec()
+        .await?;
+    fs::remove_dir_all(dir).await?;
+
+    Ok(archive)
+}
+
+/// Unpacks an archive created by [`compress`] into `dir` and removes the archive.
+pub async fn expand(archive: &Path, dir: &Path) -> Result<(), Error> {
+    println!("Expanding {:?} to {:?}", archive, dir);
+
+    fs::create_dir_all(dir).await?;
+    Cmd::new("tar")
+        .arg("-xzf")
+        .arg(archive.to_str().unwrap_or_default())
+        .args(["-C", dir.to_str().unwrap_or_default()])
+        .exec()
+        .await?;
+    fs::remove_file(archive).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn compress_and_expand() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path().join("volume");
+        std::fs::create_dir_all(dir.join(".git")).unwrap();
+        std::fs::write(dir.join("file"), "content").unwrap();
+        std::fs::write(dir.join(".git/HEAD"), "ref").unwrap();
+
+        let archive = compress(&dir).await.unwrap();
+        assert_eq!(archive, temp.path().join("volume.tar.gz"));
+        assert!(archive.exists());
+        assert!(!dir.exists());
+
+        expand(&archive, &dir).await.unwrap();
+        assert!(!archive.exists());
+        assert_eq!(
+            std::fs::read_to_string(dir.join("file")).unwrap(),
+            "content"
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.join(".git/HEAD")).unwrap(),
+            "ref"
+        );
+    }
+}
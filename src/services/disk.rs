@@ -0,0 +1,181 @@
+use std::{
+    ffi::CString,
+    io,
+    os::unix::{ffi::OsStrExt, fs::PermissionsExt},
+    path::Path,
+};
+
+use tokio::fs;
+
+const WRITE_BITS: u32 = 0o222;
+const OWNER_WRITE: u32 = 0o200;
+const EXEC_BITS: u32 = 0o111;
+const OWNER_EXEC: u32 = 0o100;
+
+/// Sums the sizes of all files under `path`. Symlinks are counted by their own size
+/// and never followed.
+pub async fn dir_size(path: &Path) -> io::Result<u64> {
+    let mut size = 0;
+    let mut pending = vec![path.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        let mut entries = fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = fs::symlink_metadata(entry.path()).await?;
+            if metadata.is_dir() {
+                pending.push(entry.path());
+            } else {
+                size += metadata.len();
+            }
+        }
+    }
+
+    Ok(size)
+}
+
+/// Bytes available to unprivileged users on the filesystem holding `path`.
+pub async fn available_space(path: &Path) -> io::Result<u64> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    tokio::task::spawn_blocking(move || {
+        let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+        // SAFETY: `path` is a valid C string and `stat` is only read after a successful call.
+        if unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let stat = unsafe { stat.assume_init() };
+        Ok(stat.f_bavail.saturating_mul(stat.f_frsize))
+    })
+    .await
+    .map_err(io::Error::other)?
+}
+
+/// Clears the write bits of everything under `path` including `path` itself, or restores
+/// the owner write bit when `readonly` is false. Symlinks are left alone.
+pub async fn set_readonly(path: &Path, readonly: bool) -> io::Result<()> {
+    let mut pending = vec![path.to_path_buf()];
+
+    while let Some(entry) = pending.pop() {
+        let metadata = fs::symlink_metadata(&entry).await?;
+        if metadata.is_symlink() {
+            continue;
+        }
+        if metadata.is_dir() {
+            let mut entries = fs::read_dir(&entry).await?;
+            while let Some(child) = entries.next_entry().await? {
+                pending.push(child.path());
+            }
+        }
+
+        let mut permissions = metadata.permissions();
+        let mode = permissions.mode();
+        permissions.set_mode(if readonly {
+            mode & !WRITE_BITS
+        } else {
+            mode | OWNER_WRITE
+        });
+        fs::set_permissions(&entry, permissions).await?;
+    }
+
+    Ok(())
+}
+
+/// Sets `mode` on `path` and every directory under it. Files get `mode` without the
+/// execute bits, unless their owner may execute them. Symlinks are left alone.
+pub async fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    let mut pending = vec![path.to_path_buf()];
+
+    while let Some(entry) = pending.pop() {
+        let metadata = fs::symlink_metadata(&entry).await?;
+        if metadata.is_symlink() {
+            continue;
+        }
+        let mut permissions = metadata.permissions();
+        if metadata.is_dir() {
+            let mut entries = fs::read_dir(&entry).await?;
+            while let Some(child) = entries.next_entry().await? {
+                pending.push(child.path());
+            }
+            permissions.set_mode(mode);
+        } else if permissions.mode() & OWNER_EXEC != 0 {
+            permissions.set_mode(mode);
+        } else {
+            permissions.set_mode(mode & !EXEC_BITS);
+        }
+        fs::set_permissions(&entry, permissions).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn sums_nested_files() {
+        let temp = tempdir().unwrap();
+        std::fs::create_dir_all(temp.path().join("a/b")).unwrap();
+        std::fs::write(temp.path().join("root"), [0u8; 10]).unwrap();
+        std::fs::write(temp.path().join("a/first"), [0u8; 100]).unwrap();
+        std::fs::write(temp.path().join("a/b/second"), [0u8; 1000]).unwrap();
+
+        assert_eq!(dir_size(temp.path()).await.unwrap(), 1110);
+    }
+
+    #[tokio::test]
+    async fn space_of_existing_path() {
+        let temp = tempdir().unwrap();
+        assert!(available_space(temp.path()).await.unwrap() > 0);
+        assert_eq!(
+            available_space(&temp.path().join("missing"))
+                .await
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+
+    #[tokio::test]
+    async fn toggle_readonly() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path().join("tree");
+        std::fs::create_dir_all(dir.join("nested")).unwrap();
+        std::fs::write(dir.join("nested/file"), "").unwrap();
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode();
+
+        set_readonly(&dir, true).await.unwrap();
+        for path in [dir.clone(), dir.join("nested"), dir.join("nested/file")] {
+            assert_eq!(mode(&path) & WRITE_BITS, 0, "{path:?}");
+        }
+
+        set_readonly(&dir, false).await.unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn apply_mode() {
+        let temp = tempdir().unwrap();
+        let dir = temp.path().join("tree");
+        std::fs::create_dir_all(dir.join("nested")).unwrap();
+        std::fs::write(dir.join("nested/file"), "").unwrap();
+        std::fs::write(dir.join("script"), "").unwrap();
+        std::fs::set_permissions(dir.join("nested"), std::fs::Permissions::from_mode(0o700))
+            .unwrap();
+        std::fs::set_permissions(dir.join("script"), std::fs::Permissions::from_mode(0o700))
+            .unwrap();
+        std::fs::set_permissions(
+            dir.join("nested/file"),
+            std::fs::Permissions::from_mode(0o600),
+        )
+        .unwrap();
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+        set_mode(&dir, 0o755).await.unwrap();
+
+        assert_eq!(mode(&dir), 0o755);
+        assert_eq!(mode(&dir.join("nested")), 0o755);
+        assert_eq!(mode(&dir.join("nested/file")), 0o644);
+        assert_eq!(mode(&dir.join("script")), 0o755);
+    }
+}
@@ -0,0 +1,235 @@
+use std::{
+    io,
+    os::unix::fs::FileTypeExt,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+use tokio::{
+    fs::{self, OpenOptions},
+    io::{AsyncWrite, AsyncWriteExt},
+    net::UnixStream,
+    sync::mpsc,
+};
+
+/// Events waiting for a slow reader, further ones are dropped.
+const BUFFER: usize = 1024;
+
+#[cfg_attr(test, derive(serde::Deserialize))]
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    Created,
+    Removed,
+    Mounted,
+    MountFailed,
+    Unmounted,
+    Refreshed,
+}
+
+/// One line of the event stream.
+#[cfg_attr(test, derive(serde::Deserialize))]
+#[derive(Debug, Serialize)]
+pub struct Event {
+    pub event: Kind,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Seconds since the unix epoch.
+    pub time: u64,
+}
+
+impl Event {
+    pub fn new(event: Kind, name: &str) -> Self {
+        Self {
+            event,
+            name: name.to_string(),
+            id: None,
+            commit: None,
+            error: None,
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|since| since.as_secs())
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn with_id(self, id: &str) -> Self {
+        Self {
+            id: Some(id.to_string()),
+            ..self
+        }
+    }
+
+    pub fn with_commit(self, commit: Option<String>) -> Self {
+        Self { commit, ..self }
+    }
+
+    pub fn with_error(self, error: impl ToString) -> Self {
+        Self {
+            error: Some(error.to_string()),
+            ..self
+        }
+    }
+}
+
+/// Sink of volume lifecycle events as newline-delimited JSON. Disabled by default.
+///
+/// Writing is best-effort: events go through a bounded queue to a background task, so a
+/// stalled reader costs dropped events, never a blocked mount.
+#[derive(Clone, Default)]
+pub struct Events {
+    sender: Option<mpsc::Sender<String>>,
+}
+
+impl Events {
+    /// Appends the events to the file or FIFO at `path`, or sends them to the unix socket
+    /// listening there. The sink is reopened after a failed write.
+    pub fn open(path: &Path) -> Self {
+        let (sender, receiver) = mpsc::channel(BUFFER);
+        tokio::spawn(write_events(path.to_path_buf(), receiver));
+        Self {
+            sender: Some(sender),
+        }
+    }
+
+    pub fn emit(&self, event: Event) {
+        let Some(sender) = &self.sender else {
+            return;
+        };
+        let mut line = match serde_json::to_string(&event) {
+            Ok(line) => line,
+            Err(e) => {
+                eprintln!("WARN: Failed to serialize event {:?}. {}", event, e);
+                return;
+            }
+        };
+        line.push('\n');
+        if sender.try_send(line).is_err() {
+            eprintln!(
+                "WARN: Event sink is not keeping up, dropped {:?} of volume {}",
+                event.event, event.name
+            );
+        }
+    }
+}
+
+type Sink = Box<dyn AsyncWrite + Unpin + Send>;
+
+async fn connect(path: &Path) -> io::Result<Sink> {
+    let is_socket = fs::metadata(path)
+        .await
+        .is_ok_and(|metadata| metadata.file_type().is_socket());
+    if is_socket {
+        return Ok(Box::new(UnixStream::connect(path).await?));
+    }
+    // Opening a FIFO waits for a reader, only this task waits with it.
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    Ok(Box::new(file))
+}
+
+async fn write_events(path: PathBuf, mut receiver: mpsc::Receiver<String>) {
+    let mut sink: Option<Sink> = None;
+
+    while let Some(line) = receiver.recv().await {
+        if sink.is_none() {
+            match connect(&path).await {
+                Ok(connected) => sink = Some(connected),
+                Err(e) => {
+                    eprintln!("WARN: Failed to open event sink {:?}. {}", path, e);
+                    continue;
+                }
+            }
+        }
+        if let Some(writer) = &mut sink {
+            let written = match writer.write_all(line.as_bytes()).await {
+                Ok(()) => writer.flush().await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = written {
+                eprintln!("WARN: Failed to write to event sink {:?}. {}", path, e);
+                sink = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use std::time::Duration;
+    use tempfile::tempdir;
+    use tokio::{io::AsyncBufReadExt, net::UnixListener};
+
+    /// Waits for the background writer to put `count` events into the file at `path`.
+    pub async fn read_events(path: &Path, count: usize) -> Vec<Event> {
+        for _ in 0..100 {
+            let content = std::fs::read_to_string(path).unwrap_or_default();
+            if content.lines().count() >= count {
+                return content
+                    .lines()
+                    .map(|line| serde_json::from_str(line).unwrap())
+                    .collect();
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("{count} events were not written to {path:?}");
+    }
+
+    #[tokio::test]
+    async fn appends_to_file() {
+        let temp = tempdir().unwrap();
+        let path = temp.path().join("events");
+        std::fs::write(&path, "").unwrap();
+        let events = Events::open(&path);
+
+        events.emit(Event::new(Kind::Created, "first"));
+        events.emit(
+            Event::new(Kind::MountFailed, "second")
+                .with_id("id")
+                .with_error("clone failed"),
+        );
+
+        let written = read_events(&path, 2).await;
+        assert!(matches!(written[0].event, Kind::Created));
+        assert_eq!(written[0].name, "first");
+        assert_eq!(written[0].error, None);
+        assert!(matches!(written[1].event, Kind::MountFailed));
+        assert_eq!(written[1].id.as_deref(), Some("id"));
+        assert_eq!(written[1].error.as_deref(), Some("clone failed"));
+    }
+
+    #[tokio::test]
+    async fn sends_to_socket() {
+        let temp = tempdir().unwrap();
+        let path = temp.path().join("events.sock");
+        let listener = UnixListener::bind(&path).unwrap();
+        let events = Events::open(&path);
+
+        events.emit(Event::new(Kind::Removed, "volume"));
+
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut line = String::new();
+        tokio::io::BufReader::new(stream)
+            .read_line(&mut line)
+            .await
+            .unwrap();
+        let event: Event = serde_json::from_str(&line).unwrap();
+        assert!(matches!(event.event, Kind::Removed));
+        assert_eq!(event.name, "volume");
+    }
+
+    #[tokio::test]
+    async fn disabled_sink_drops_events() {
+        Events::default().emit(Event::new(Kind::Created, "volume"));
+    }
+}
@@ -0,0 +1,181 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use tokio::fs;
+
+/// Renames `from` to `to`. When they are on different filesystems, e.g. the mount base is
+/// a bind mount, the rename fails with `EXDEV` and the tree is copied and removed instead.
+/// The fallback is not atomic, a crash in the middle may leave both copies behind.
+pub async fn move_path(from: &Path, to: &Path) -> io::Result<()> {
+    move_path_with(
+        from,
+        to,
+        |from, to| async move { fs::rename(from, to).await },
+    )
+    .await
+}
+
+async fn move_path_with<F, Fut>(from: &Path, to: &Path, rename: F) -> io::Result<()>
+where
+    F: FnOnce(PathBuf, PathBuf) -> Fut,
+    Fut: Future<Output = io::Result<()>>,
+{
+    match rename(from.to_path_buf(), to.to_path_buf()).await {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            println!("Copying {:?} to {:?} across filesystems", from, to);
+            let existed = fs::symlink_metadata(to).await.is_ok();
+            if let Err(e) = copy_tree(from, to).await {
+                if !existed {
+                    _ = remove_all(to).await;
+                }
+                return Err(e);
+            }
+            remove_all(from).await
+        }
+        result => result,
+    }
+}
+
+/// Copies a file or a directory tree keeping permissions. Symlinks are copied as links.
+async fn copy_tree(from: &Path, to: &Path) -> io::Result<()> {
+    let mut pending = vec![(from.to_path_buf(), to.to_path_buf())];
+    // Directory permissions are applied last, a read-only directory could not be filled.
+    let mut dirs = Vec::new();
+
+    while let Some((source, target)) = pending.pop() {
+        let metadata = fs::symlink_metadata(&source).await?;
+        if metadata.is_symlink() {
+            fs::symlink(fs::read_link(&source).await?, &target).await?;
+        } else if metadata.is_dir() {
+            fs::create_dir(&target).await?;
+            let mut entries = fs::read_dir(&source).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                pending.push((entry.path(), target.join(entry.file_name())));
+            }
+            dirs.push((target, metadata.permissions()));
+        } else {
+            fs::copy(&source, &target).await?;
+        }
+    }
+
+    for (dir, permissions) in dirs.into_iter().rev() {
+        fs::set_permissions(&dir, permissions).await?;
+    }
+
+    Ok(())
+}
+
+/// Removes a file or a directory tree, a missing path is not an error.
+pub async fn remove_all(path: &Path) -> io::Result<()> {
+    let result = match fs::symlink_metadata(path).await {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path).await,
+        Ok(_) => fs::remove_file(path).await,
+        Err(e) => Err(e),
+    };
+    match result {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{
+        os::unix::fs::PermissionsExt,
+        sync::atomic::{AtomicBool, Ordering},
+    };
+    use tempfile::tempdir;
+
+    fn tree(dir: &Path) {
+        std::fs::create_dir_all(dir.join("nested")).unwrap();
+        std::fs::write(dir.join("nested/file"), "content").unwrap();
+        std::fs::write(dir.join("script"), "").unwrap();
+        std::fs::set_permissions(dir.join("script"), std::fs::Permissions::from_mode(0o755))
+            .unwrap();
+        std::os::unix::fs::symlink("nested/file", dir.join("link")).unwrap();
+    }
+
+    #[tokio::test]
+    async fn move_on_same_filesystem() {
+        let temp = tempdir().unwrap();
+        let (from, to) = (temp.path().join("from"), temp.path().join("to"));
+        tree(&from);
+
+        move_path(&from, &to).await.unwrap();
+
+        assert!(!from.exists());
+        assert_eq!(std::fs::read_to_string(to.join("link")).unwrap(), "content");
+    }
+
+    #[tokio::test]
+    async fn cross_device_falls_back_to_copy() {
+        let temp = tempdir().unwrap();
+        let (from, to) = (temp.path().join("from"), temp.path().join("to"));
+        tree(&from);
+        std::fs::set_permissions(&from, std::fs::Permissions::from_mode(0o750)).unwrap();
+
+        let renamed = AtomicBool::new(false);
+        move_path_with(&from, &to, |_, _| async {
+            renamed.store(true, Ordering::SeqCst);
+            Err(io::ErrorKind::CrossesDevices.into())
+        })
+        .await
+        .unwrap();
+
+        assert!(renamed.load(Ordering::SeqCst));
+        assert!(!from.exists());
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&to), 0o750);
+        assert_eq!(mode(&to.join("script")), 0o755);
+        assert_eq!(
+            std::fs::read_link(to.join("link")).unwrap(),
+            Path::new("nested/file")
+        );
+        assert_eq!(std::fs::read_to_string(to.join("link")).unwrap(), "content");
+    }
+
+    #[tokio::test]
+    async fn cross_device_file() {
+        let temp = tempdir().unwrap();
+        let (from, to) = (
+            temp.path().join("repo.json.tmp"),
+            temp.path().join("repo.json"),
+        );
+        std::fs::write(&from, "new").unwrap();
+        std::fs::write(&to, "old").unwrap();
+
+        move_path_with(&from, &to, |_, _| async {
+            Err(io::ErrorKind::CrossesDevices.into())
+        })
+        .await
+        .unwrap();
+
+        assert!(!from.exists());
+        assert_eq!(std::fs::read_to_string(&to).unwrap(), "new");
+    }
+
+    #[tokio::test]
+    async fn other_rename_errors_are_returned() {
+        let temp = tempdir().unwrap();
+        let (from, to) = (temp.path().join("from"), temp.path().join("to"));
+        tree(&from);
+
+        let result = move_path_with(&from, &to, |_, _| async {
+            Err(io::ErrorKind::PermissionDenied.into())
+        })
+        .await;
+
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+        assert!(from.exists());
+        assert!(!to.exists());
+    }
+
+    #[tokio::test]
+    async fn remove_missing_is_ok() {
+        let temp = tempdir().unwrap();
+        remove_all(&temp.path().join("missing")).await.unwrap();
+    }
+}
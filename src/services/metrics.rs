@@ -0,0 +1,137 @@
+use std::{
+    fmt::Write,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+/// Upper bounds in seconds of the clone duration buckets.
+const CLONE_BUCKETS: [f64; 10] = [0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0];
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; CLONE_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+/// Counters rendered in the Prometheus text format by `/metrics`.
+#[derive(Default)]
+pub struct Metrics {
+    clone_success: AtomicU64,
+    clone_failure: AtomicU64,
+    clone_duration: Mutex<Histogram>,
+}
+
+impl Metrics {
+    pub fn observe_clone(&self, succeeded: bool, duration: Duration) {
+        let counter = if succeeded {
+            &self.clone_success
+        } else {
+            &self.clone_failure
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+
+        let seconds = duration.as_secs_f64();
+        let mut histogram = self
+            .clone_duration
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        for (bucket, le) in histogram.buckets.iter_mut().zip(CLONE_BUCKETS) {
+            if seconds <= le {
+                *bucket += 1;
+            }
+        }
+        histogram.sum += seconds;
+        histogram.count += 1;
+    }
+
+    /// `volumes_active` is taken by the caller from the volume map, it is not tracked here.
+    pub fn render(&self, volumes_active: usize) -> String {
+        let mut out = String::new();
+
+        _ = writeln!(
+            out,
+            "# HELP gitvol_clone_total Clones made on mount, by result."
+        );
+        _ = writeln!(out, "# TYPE gitvol_clone_total counter");
+        _ = writeln!(
+            out,
+            "gitvol_clone_total{{result=\"success\"}} {}",
+            self.clone_success.load(Ordering::Relaxed)
+        );
+        _ = writeln!(
+            out,
+            "gitvol_clone_total{{result=\"failure\"}} {}",
+            self.clone_failure.load(Ordering::Relaxed)
+        );
+
+        let histogram = self
+            .clone_duration
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        _ = writeln!(
+            out,
+            "# HELP gitvol_clone_duration_seconds Duration of clones made on mount."
+        );
+        _ = writeln!(out, "# TYPE gitvol_clone_duration_seconds histogram");
+        for (count, le) in histogram.buckets.iter().zip(CLONE_BUCKETS) {
+            _ = writeln!(
+                out,
+                "gitvol_clone_duration_seconds_bucket{{le=\"{le}\"}} {count}"
+            );
+        }
+        _ = writeln!(
+            out,
+            "gitvol_clone_duration_seconds_bucket{{le=\"+Inf\"}} {}",
+            histogram.count
+        );
+        _ = writeln!(out, "gitvol_clone_duration_seconds_sum {}", histogram.sum);
+        _ = writeln!(
+            out,
+            "gitvol_clone_duration_seconds_count {}",
+            histogram.count
+        );
+
+        _ = writeln!(
+            out,
+            "# HELP gitvol_volumes_active Volumes mounted by at least one container."
+        );
+        _ = writeln!(out, "# TYPE gitvol_volumes_active gauge");
+        _ = writeln!(out, "gitvol_volumes_active {volumes_active}");
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn render_empty() {
+        let out = Metrics::default().render(0);
+        assert!(out.contains("gitvol_clone_total{result=\"success\"} 0\n"));
+        assert!(out.contains("gitvol_clone_duration_seconds_count 0\n"));
+        assert!(out.contains("gitvol_volumes_active 0\n"));
+    }
+
+    #[test]
+    fn observe_clone() {
+        let metrics = Metrics::default();
+        metrics.observe_clone(true, Duration::from_millis(700));
+        metrics.observe_clone(false, Duration::from_secs(20));
+
+        let out = metrics.render(3);
+        assert!(out.contains("gitvol_clone_total{result=\"success\"} 1\n"));
+        assert!(out.contains("gitvol_clone_total{result=\"failure\"} 1\n"));
+        assert!(out.contains("gitvol_clone_duration_seconds_bucket{le=\"0.5\"} 0\n"));
+        assert!(out.contains("gitvol_clone_duration_seconds_bucket{le=\"1\"} 1\n"));
+        assert!(out.contains("gitvol_clone_duration_seconds_bucket{le=\"30\"} 2\n"));
+        assert!(out.contains("gitvol_clone_duration_seconds_bucket{le=\"+Inf\"} 2\n"));
+        assert!(out.contains("gitvol_clone_duration_seconds_sum 20.7\n"));
+        assert!(out.contains("gitvol_volumes_active 3\n"));
+    }
+}
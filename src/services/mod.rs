@@ -1,2 +1,8 @@
+pub mod archive;
+pub mod disk;
+pub mod events;
+pub mod fsutil;
 pub mod git;
+pub mod metrics;
+pub mod store;
 pub mod volumes;
@@ -0,0 +1,394 @@
+use std::{
+    collections::HashSet,
+    io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{fs, io::AsyncWriteExt};
+
+use super::fsutil;
+use crate::domains::{
+    timestamp,
+    volume::{Status, Volume},
+};
+
+pub const REPO_FILE: &str = "repo.json";
+const TEMP_FILE: &str = "repo.json.tmp";
+/// Marks a directory as the volume directory of gitvol, see `mark`.
+pub const MARKER_FILE: &str = ".gitvol";
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Failed to access volume state {path:?}: {source}")]
+    Io { path: PathBuf, source: io::Error },
+
+    #[error("Failed to serialize volume state: {0}")]
+    Serialize(#[from] serde_json::Error),
+}
+
+fn io_error(path: &Path) -> impl FnOnce(io::Error) -> Error {
+    let path = path.to_path_buf();
+    move |source| Error::Io { path, source }
+}
+
+/// Writes the volume state to `<volume dir>/repo.json`, only readable by the owner. Secrets
+/// are written as `***`, see `Secret`.
+///
+/// The content goes to a temporary sibling first and is renamed into place once synced,
+/// so a crash mid-save leaves the previous state intact.
+pub async fn save(base_path: &Path, volume: &Volume) -> Result<(), Error> {
+    let dir = volume.dir(base_path);
+    fs::create_dir_all(&dir).await.map_err(io_error(&dir))?;
+
+    let content = serde_json::to_vec_pretty(volume)?;
+    let temp_path = dir.join(TEMP_FILE);
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&temp_path)
+        .await
+        .map_err(io_error(&temp_path))?;
+    file.write_all(&content)
+        .await
+        .map_err(io_error(&temp_path))?;
+    file.sync_all().await.map_err(io_error(&temp_path))?;
+    drop(file);
+
+    let repo_path = dir.join(REPO_FILE);
+    fsutil::move_path(&temp_path, &repo_path)
+        .await
+        .map_err(io_error(&repo_path))?;
+
+    Ok(())
+}
+
+/// Content of `MARKER_FILE`, tells which volume a directory belongs to without its state.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Marker {
+    pub name: String,
+    pub hash: String,
+    pub created_at: String,
+}
+
+/// Writes the `MARKER_FILE` of the volume into its directory under `base_path`, unless it
+/// is there already. It sits next to the clone, the checkout itself is left alone.
+pub async fn mark(base_path: &Path, volume: &Volume) -> Result<(), Error> {
+    let dir = volume.dir(base_path);
+    let marker_path = dir.join(MARKER_FILE);
+    if fs::try_exists(&marker_path).await.unwrap_or(false) {
+        return Ok(());
+    }
+    fs::create_dir_all(&dir).await.map_err(io_error(&dir))?;
+
+    let marker = Marker {
+        name: volume.name.clone(),
+        hash: volume.hash(),
+        created_at: timestamp::rfc3339(volume.created_at),
+    };
+    fs::write(&marker_path, serde_json::to_vec_pretty(&marker)?)
+        .await
+        .map_err(io_error(&marker_path))
+}
+
+async fn read_marker(dir: &Path) -> Option<Marker> {
+    let content = fs::read(dir.join(MARKER_FILE)).await.ok()?;
+    serde_json::from_slice(&content).ok()
+}
+
+/// Removes the state file, the volume data itself is owned by the caller.
+pub async fn delete(base_path: &Path, volume: &Volume) -> Result<(), Error> {
+    let repo_path = volume.dir(base_path).join(REPO_FILE);
+    match fs::remove_file(&repo_path).await {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(io_error(&repo_path)(e)),
+        _ => Ok(()),
+    }
+}
+
+async fn read(dir: &Path) -> Option<Volume> {
+    let content = fs::read(dir.join(REPO_FILE)).await.ok()?;
+    serde_json::from_slice(&content).ok()
+}
+
+async fn prune(dir: &Path, reason: &str) {
+    eprintln!("WARN: Pruning volume directory {:?}. {}", dir, reason);
+    if let Err(e) = fs::remove_dir_all(dir).await {
+        eprintln!("WARN: Failed to prune {:?}. {}", dir, e);
+    }
+}
+
+/// Restores the volumes saved under `base_path`. Marked directories without a readable state
+/// file are leftovers of an interrupted operation and get removed, unmarked ones are not
+/// gitvol's and are left alone. Hidden entries are skipped.
+pub async fn load(base_path: &Path) -> Result<Vec<Volume>, Error> {
+    let mut volumes = Vec::new();
+    let mut names = HashSet::new();
+
+    let mut entries = fs::read_dir(base_path).await.map_err(io_error(base_path))?;
+    while let Some(entry) = entries.next_entry().await.map_err(io_error(base_path))? {
+        let mut dir = entry.path();
+        let hidden = entry.file_name().to_string_lossy().starts_with('.');
+        if hidden || !dir.is_dir() {
+            continue;
+        }
+
+        let Some(mut volume) = read(&dir).await else {
+            match read_marker(&dir).await {
+                Some(marker) => {
+                    let reason = format!("Orphaned directory of volume {}.", marker.name);
+                    prune(&dir, &reason).await;
+                }
+                None => eprintln!(
+                    "WARN: Skipping directory {:?}, it has no volume state nor gitvol marker.",
+                    dir
+                ),
+            }
+            continue;
+        };
+        if volume.legacy_dir(base_path) == dir {
+            match migrate(base_path, &dir, &mut volume).await {
+                Ok(migrated) => dir = migrated,
+                Err(e) => {
+                    eprintln!("WARN: Failed to migrate volume directory {:?}. {}", dir, e);
+                    continue;
+                }
+            }
+        }
+        if volume.dir(base_path) != dir || !names.insert(volume.name.clone()) {
+            prune(&dir, "State file does not belong to this directory.").await;
+            continue;
+        }
+
+        if volume.path.as_ref().is_some_and(|path| !path.exists()) {
+            volume.path = None;
+            volume.commit = None;
+            volume.status = Status::Cleared;
+        }
+        if volume.archive.as_ref().is_some_and(|path| !path.exists()) {
+            volume.archive = None;
+        }
+        // The plugin stopped in the middle of a clone, the next mount starts over.
+        if matches!(volume.status, Status::Cloning) {
+            volume.status = Status::Created;
+        }
+
+        println!("Restored volume {} from {:?}", volume.name, dir);
+        volumes.push(volume);
+    }
+
+    Ok(volumes)
+}
+
+/// Moves a volume directory named by the old, unstable hash to its current name.
+async fn migrate(base_path: &Path, dir: &Path, volume: &mut Volume) -> Result<PathBuf, Error> {
+    let migrated = volume.dir(base_path);
+    fsutil::move_path(dir, &migrated)
+        .await
+        .map_err(io_error(&migrated))?;
+
+    let relocate = |path: PathBuf| match path.strip_prefix(dir) {
+        Ok(rest) => migrated.join(rest),
+        Err(_) => path,
+    };
+    volume.path = volume.path.take().map(relocate);
+    volume.archive = volume.archive.take().map(relocate);
+    save(base_path, volume).await?;
+
+    println!("Migrated volume {} to {:?}", volume.name, migrated);
+    Ok(migrated)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::domains::{repo::RawRepo, volume::DirNaming};
+    use rstest::rstest;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::tempdir;
+
+    fn volume(name: &str) -> Volume {
+        Volume::try_from((
+            name,
+            RawRepo {
+                token: Some("s3cr3t".into()),
+                ..RawRepo::stub()
+            },
+        ))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn save_and_load() {
+        let temp = tempdir().unwrap();
+        let mut mounted = volume("mounted");
+        let path = mounted.data_path(temp.path());
+        std::fs::create_dir_all(&path).unwrap();
+        mounted.path = Some(path);
+        mounted.status = Status::Clonned;
+        mounted.commit = Some("abc".into());
+        mounted.containers.insert("id".into());
+        let mut cleared = volume("cleared");
+        cleared.path = Some(cleared.data_path(temp.path()));
+
+        save(temp.path(), &mounted).await.unwrap();
+        save(temp.path(), &cleared).await.unwrap();
+
+        let mode = std::fs::metadata(mounted.dir(temp.path()).join(REPO_FILE))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let mut loaded = load(temp.path()).await.unwrap();
+        loaded.sort_by(|a, b| a.name.cmp(&b.name));
+        assert_eq!(loaded.len(), 2);
+
+        // The secret is not saved, the loaded volume knows it had one.
+        assert!(loaded[1].repo.lost_auth());
+        let mounted = Volume {
+            repo: mounted.repo.masked_auth(),
+            ..mounted
+        };
+        assert_eq!(loaded[1], mounted);
+        assert_eq!(loaded[0].name, "cleared");
+        assert_eq!(loaded[0].path, None);
+        assert_eq!(loaded[0].status, Status::Cleared);
+    }
+
+    #[rstest]
+    #[case(RawRepo { token: Some("s3cr3t".into()), ..RawRepo::stub() })]
+    #[case(RawRepo { username: Some("user".into()), password: Some("s3cr3t".into()), ..RawRepo::stub() })]
+    #[tokio::test]
+    async fn save_keeps_secrets_out(#[case] raw: RawRepo) {
+        let temp = tempdir().unwrap();
+        let volume = Volume::try_from(("private", raw)).unwrap();
+
+        save(temp.path(), &volume).await.unwrap();
+
+        let content = std::fs::read_to_string(volume.dir(temp.path()).join(REPO_FILE)).unwrap();
+        assert!(!content.contains("s3cr3t"), "{content}");
+    }
+
+    #[tokio::test]
+    async fn load_readable_dir() {
+        let temp = tempdir().unwrap();
+        let volume = volume("docs").with_dir_naming(DirNaming::Readable);
+        save(temp.path(), &volume).await.unwrap();
+
+        let loaded = load(temp.path()).await.unwrap();
+
+        let volume = Volume {
+            repo: volume.repo.masked_auth(),
+            ..volume
+        };
+        assert_eq!(loaded, vec![volume.clone()]);
+        assert!(
+            temp.path()
+                .join(volume.dir_name.unwrap())
+                .join(REPO_FILE)
+                .exists()
+        );
+    }
+
+    #[tokio::test]
+    async fn load_state_without_timestamps() {
+        let temp = tempdir().unwrap();
+        let volume = volume("old");
+        save(temp.path(), &volume).await.unwrap();
+
+        let file = volume.dir(temp.path()).join(REPO_FILE);
+        let mut json: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&file).unwrap()).unwrap();
+        let state = json.as_object_mut().unwrap();
+        state.remove("created_at").unwrap();
+        state.remove("updated_at").unwrap();
+        std::fs::write(&file, json.to_string()).unwrap();
+
+        let loaded = load(temp.path()).await.unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert!(loaded[0].created_at >= volume.created_at);
+    }
+
+    #[tokio::test]
+    async fn interrupted_save_keeps_previous_state() {
+        let temp = tempdir().unwrap();
+        let mut volume = volume("volume");
+        save(temp.path(), &volume).await.unwrap();
+        let dir = volume.dir(temp.path());
+        assert!(!dir.join(TEMP_FILE).exists());
+
+        // A save that crashed after writing part of the new content.
+        volume.commit = Some("new".into());
+        let content = serde_json::to_vec(&volume).unwrap();
+        std::fs::write(dir.join(TEMP_FILE), &content[..content.len() / 2]).unwrap();
+
+        let loaded = load(temp.path()).await.unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].commit, None);
+
+        save(temp.path(), &volume).await.unwrap();
+        let loaded = load(temp.path()).await.unwrap();
+        assert_eq!(loaded[0].commit, Some("new".into()));
+        assert!(!dir.join(TEMP_FILE).exists());
+    }
+
+    #[tokio::test]
+    async fn load_migrates_legacy_dirs() {
+        let temp = tempdir().unwrap();
+        let mut volume = volume("legacy");
+        let legacy = volume.legacy_dir(temp.path());
+        std::fs::create_dir_all(legacy.join("data")).unwrap();
+        std::fs::write(legacy.join("data/file"), "content").unwrap();
+        volume.path = Some(legacy.join("data"));
+        volume.status = Status::Clonned;
+        std::fs::write(legacy.join(REPO_FILE), serde_json::to_vec(&volume).unwrap()).unwrap();
+
+        let loaded = load(temp.path()).await.unwrap();
+
+        let dir = volume.dir(temp.path());
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].path, Some(dir.join("data")));
+        assert!(matches!(loaded[0].status, Status::Clonned));
+        assert!(!legacy.exists());
+        assert_eq!(
+            std::fs::read_to_string(dir.join("data/file")).unwrap(),
+            "content"
+        );
+
+        let reloaded = load(temp.path()).await.unwrap();
+        assert_eq!(reloaded[0].path, Some(dir.join("data")));
+    }
+
+    #[tokio::test]
+    async fn load_prunes_invalid_entries() {
+        let temp = tempdir().unwrap();
+        let valid = volume("valid");
+        save(temp.path(), &valid).await.unwrap();
+
+        let missing = temp.path().join("missing");
+        std::fs::create_dir_all(missing.join("data")).unwrap();
+        let corrupt = volume("corrupt");
+        mark(temp.path(), &corrupt).await.unwrap();
+        let corrupt = corrupt.dir(temp.path());
+        std::fs::write(corrupt.join(REPO_FILE), "{\"name\": \"trunc").unwrap();
+        let foreign = temp.path().join("foreign");
+        std::fs::create_dir_all(&foreign).unwrap();
+        std::fs::write(foreign.join(REPO_FILE), "{\"name\": \"trunc").unwrap();
+        let orphan = volume("orphan");
+        mark(temp.path(), &orphan).await.unwrap();
+        let hidden = temp.path().join(".objects");
+        std::fs::create_dir_all(&hidden).unwrap();
+
+        let loaded = load(temp.path()).await.unwrap();
+
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].name, "valid");
+        assert!(missing.join("data").exists());
+        assert!(foreign.join(REPO_FILE).exists());
+        assert!(!corrupt.exists());
+        assert!(!orphan.dir(temp.path()).exists());
+        assert!(hidden.exists());
+    }
+}
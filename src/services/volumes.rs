@@ -1,10 +1,22 @@
-use crate::domains::{repo::RawRepo, volume::Volume};
-use std::{collections::HashMap, sync::Arc};
-use tokio::sync::{OwnedRwLockReadGuard, OwnedRwLockWriteGuard, RwLock};
+use crate::{
+    domains::{
+        repo::{RawRepo, Repo},
+        url::{Error as UrlError, HostPolicy},
+        volume::{DirNaming, Error as VolumeError, Status, Volume},
+    },
+    services::store,
+};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+use tokio::sync::{Mutex, OwnedRwLockReadGuard, OwnedRwLockWriteGuard, RwLock};
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
-    #[error("Volume named {0} already exists")]
+    #[error("Volume named {0} already exists with different options")]
     AlreadyExists(String),
 
     #[error("Non existen volume named {0}")]
@@ -12,20 +24,115 @@ pub enum Error {
 
     #[error(transparent)]
     Volume(#[from] crate::domains::volume::Error),
+
+    #[error(transparent)]
+    Store(#[from] store::Error),
+
+    #[error(transparent)]
+    Url(#[from] UrlError),
+
+    #[error("Volume limit of {0} reached")]
+    LimitReached(usize),
+
+    #[error("Volume named {0} is in use, it can only be changed while unmounted")]
+    InUse(String),
+
+    #[error("Volume {name} is in use by {count} container(s)")]
+    Mounted { name: String, count: usize },
+}
+
+impl Error {
+    /// Stable name of the failure for tooling, see `git::Error::code`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::AlreadyExists(_) => "ALREADY_EXISTS",
+            Self::NonExists(_) => "VOLUME_NOT_FOUND",
+            Self::Volume(_) => "INVALID_OPTIONS",
+            Self::Url(UrlError::HostNotAllowed(_)) => "HOST_NOT_ALLOWED",
+            Self::Url(_) => "INVALID_OPTIONS",
+            Self::Store(_) => "STORE",
+            Self::LimitReached(_) => "LIMIT_REACHED",
+            Self::InUse(_) | Self::Mounted { .. } => "IN_USE",
+        }
+    }
 }
 
 type Vol = Arc<RwLock<Volume>>;
 type VolMap = HashMap<String, Vol>;
+type Gate = Arc<Mutex<()>>;
 
 #[derive(Clone)]
 pub struct Volumes {
     inner: Arc<RwLock<VolMap>>,
+    gates: Arc<Mutex<HashMap<String, Gate>>>,
+    store: Option<PathBuf>,
+    hosts: HostPolicy,
+    max_volumes: Option<usize>,
+    dir_naming: DirNaming,
 }
 
 impl Volumes {
     pub fn new() -> Self {
         Self {
             inner: Arc::new(RwLock::new(HashMap::new())),
+            gates: Arc::new(Mutex::new(HashMap::new())),
+            store: None,
+            hosts: HostPolicy::default(),
+            max_volumes: None,
+            dir_naming: DirNaming::default(),
+        }
+    }
+
+    /// Restores the volumes saved under `base_path` and keeps saving changes there.
+    pub async fn load(base_path: &Path) -> Result<Self, Error> {
+        let map = store::load(base_path)
+            .await?
+            .into_iter()
+            .map(|volume| (volume.name.clone(), Arc::new(RwLock::new(volume))))
+            .collect();
+
+        Ok(Self {
+            inner: Arc::new(RwLock::new(map)),
+            store: Some(base_path.to_path_buf()),
+            ..Self::new()
+        })
+    }
+
+    /// Hosts new volumes may be created for.
+    pub fn with_host_policy(self, hosts: HostPolicy) -> Self {
+        Self { hosts, ..self }
+    }
+
+    pub fn with_max_volumes(self, max_volumes: Option<usize>) -> Self {
+        Self {
+            max_volumes,
+            ..self
+        }
+    }
+
+    /// Naming of the directories of new and updated volumes, existing ones keep theirs.
+    pub fn with_dir_naming(self, dir_naming: DirNaming) -> Self {
+        Self { dir_naming, ..self }
+    }
+
+    /// Lock serializing the clone of a volume without locking the volume itself.
+    pub async fn clone_gate(&self, name: &str) -> Gate {
+        let mut gates = self.gates.lock().await;
+        gates.entry(name.to_string()).or_default().clone()
+    }
+
+    /// Persists the volume state, a no-op for in-memory volumes.
+    ///
+    /// Best-effort: the change already happened on disk, failing the request because its
+    /// record could not be written would leave docker and the plugin out of sync.
+    pub async fn save(&self, volume: &Volume) {
+        if let Some(base_path) = &self.store
+            && let Err(e) = store::save(base_path, volume).await
+        {
+            eprintln!(
+                "WARN: Failed to save state of volume {}. {}",
+                volume.name, e
+            );
         }
     }
 
@@ -43,36 +150,154 @@ impl Volumes {
         volume.cloned()
     }
 
+    /// Parses `raw` the way `create` does, without creating a volume.
+    pub fn validate(&self, raw: RawRepo) -> Result<Repo, Error> {
+        let repo = Repo::try_from(raw).map_err(VolumeError::from)?;
+        self.check_allowed(&repo)?;
+        Ok(repo)
+    }
+
+    /// Fails for a repository on a host the policy does not allow. Checked again before
+    /// reaching the remote, the policy may have been tightened since the volume was created.
+    pub fn check_allowed(&self, repo: &Repo) -> Result<(), Error> {
+        Ok(repo.url.check_allowed(&self.hosts)?)
+    }
+
+    /// Creates the volume, or returns the existing one when Docker repeats the create with
+    /// the same options. The flag tells whether the volume is new, decided under the map
+    /// lock so concurrent creates agree on it.
     pub async fn create(
         &self,
         name: &str,
         raw: Option<RawRepo>,
-    ) -> Result<OwnedRwLockWriteGuard<Volume>, Error> {
+    ) -> Result<(OwnedRwLockWriteGuard<Volume>, bool), Error> {
         let mut volumes = self.write_map().await;
 
-        let volume = Volume::try_from((name, raw))?;
-
-        if volumes.contains_key(&volume.name) {
-            return Err(Error::AlreadyExists(name.to_string()));
+        let volume = Volume::try_from((name, raw))?.with_dir_naming(self.dir_naming);
+        self.check_allowed(&volume.repo)?;
+
+        // Docker may repeat a create, only different options make it a conflict. Credentials
+        // are not kept in the state file, a repeated create after a restart gives them back.
+        if let Some(existing) = volumes.get(&volume.name).cloned() {
+            drop(volumes);
+            let mut existing = existing.write_owned().await;
+            if existing.repo.lost_auth() && existing.repo == volume.repo.masked_auth() {
+                existing.repo.auth = volume.repo.auth.clone();
+            }
+            if existing.repo != volume.repo {
+                return Err(Error::AlreadyExists(name.to_string()));
+            }
+            return Ok((existing, false));
+        }
+        if let Some(max) = self.max_volumes
+            && volumes.len() >= max
+        {
+            return Err(Error::LimitReached(max));
         }
 
+        self.save(&volume).await;
+
         let volume = Arc::new(RwLock::new(volume));
         volumes.insert(name.to_string(), volume.clone());
 
-        Ok(volume.write_owned().await)
+        Ok((volume.write_owned().await, true))
+    }
+
+    /// Replaces the repository of an unused volume, it is cloned again on the next mount.
+    /// Returns the volume as it was before, the caller removes its directory.
+    pub async fn update(
+        &self,
+        name: &str,
+        raw: RawRepo,
+    ) -> Result<(Volume, OwnedRwLockWriteGuard<Volume>), Error> {
+        let repo = self.validate(raw)?;
+        let mut volume = self.try_write(name).await?;
+        let cloning = self.clone_gate(name).await.try_lock().is_err();
+        if cloning || !volume.containers.is_empty() {
+            return Err(Error::InUse(name.to_string()));
+        }
+
+        let previous = volume.clone();
+        volume.repo = repo;
+        volume.dir_name = volume.clone().with_dir_naming(self.dir_naming).dir_name;
+        volume.data_base = None;
+        volume.path = None;
+        volume.archive = None;
+        volume.commit = None;
+        volume.default_branch = None;
+        volume.tag = false;
+        volume.status = Status::Created;
+        volume.updated_at = SystemTime::now();
+        volume.last_refetch = None;
+
+        Ok((previous, volume))
     }
 
-    pub async fn remove(&self, name: &str) -> Option<Volume> {
+    /// Removes an unmounted volume, `None` when there is none. The containers are checked
+    /// under the same locks, a mount can not slip in before the removal.
+    pub async fn remove(&self, name: &str) -> Result<Option<Volume>, Error> {
         let mut list = self.write_map().await;
 
-        let locked_volume = list.get(name)?;
-        let volume_guard = locked_volume.read().await;
+        let Some(locked_volume) = list.get(name) else {
+            return Ok(None);
+        };
+        let volume_guard = locked_volume.write().await;
+        if !volume_guard.containers.is_empty() {
+            return Err(Error::Mounted {
+                name: name.to_string(),
+                count: volume_guard.containers.len(),
+            });
+        }
 
         let cloned_volume = volume_guard.clone();
         drop(volume_guard);
         list.remove(name);
+        self.gates.lock().await.remove(name);
 
-        Some(cloned_volume)
+        if let Some(base_path) = &self.store
+            && let Err(e) = store::delete(base_path, &cloned_volume).await
+        {
+            eprintln!("WARN: Failed to delete state of volume {}. {}", name, e);
+        }
+
+        Ok(Some(cloned_volume))
+    }
+
+    /// Removes the cleared volumes no container uses and returns them. Volumes locked by a
+    /// mount or clone in progress are skipped.
+    pub async fn prune(&self) -> Vec<Volume> {
+        let mut map = self.write_map().await;
+        let mut gates = self.gates.lock().await;
+
+        let mut pruned = Vec::new();
+        map.retain(|name, volume| {
+            let cloning = gates.get(name).is_some_and(|gate| gate.try_lock().is_err());
+            let Ok(volume) = volume.try_read() else {
+                return true;
+            };
+            if cloning || !matches!(volume.status, Status::Cleared) || !volume.containers.is_empty()
+            {
+                return true;
+            }
+            gates.remove(name);
+            pruned.push(volume.clone());
+            false
+        });
+        drop(gates);
+        drop(map);
+
+        if let Some(base_path) = &self.store {
+            for volume in &pruned {
+                if let Err(e) = store::delete(base_path, volume).await {
+                    eprintln!(
+                        "WARN: Failed to delete state of volume {}. {}",
+                        volume.name, e
+                    );
+                }
+            }
+        }
+
+        pruned
     }
 
     pub async fn read(&self, name: &str) -> Option<OwnedRwLockReadGuard<Volume>> {
@@ -81,6 +306,10 @@ impl Volumes {
         Some(guard)
     }
 
+    pub async fn count(&self) -> usize {
+        self.read_map().await.len()
+    }
+
     pub async fn read_all(&self) -> Vec<Volume> {
         let map = self.read_map().await;
         let mut list: Vec<Volume> = Vec::with_capacity(map.len());
@@ -93,6 +322,21 @@ impl Volumes {
         list
     }
 
+    /// Like `read_all` followed by `filter_map`, but locks one volume at a time and clones
+    /// only what `f` picks.
+    pub async fn filter_map<T>(&self, mut f: impl FnMut(&Volume) -> Option<T>) -> Vec<T> {
+        let map = self.read_map().await;
+        let mut list = Vec::new();
+
+        for volume in map.values() {
+            if let Some(item) = f(&*volume.read().await) {
+                list.push(item);
+            }
+        }
+
+        list
+    }
+
     pub async fn write(&self, name: &str) -> Option<OwnedRwLockWriteGuard<Volume>> {
         let volume = self.get(name).await?;
         let guard = volume.write_owned().await;
@@ -113,10 +357,7 @@ impl Volumes {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::domains::{
-        repo::test::REPO_URL,
-        volume::{Status, test::VOLUME_NAME},
-    };
+    use crate::domains::{repo::test::REPO_URL, volume::test::VOLUME_NAME};
     use rstest::rstest;
 
     #[tokio::test]
@@ -130,6 +371,58 @@ mod test {
         assert_eq!(map.len(), 0);
     }
 
+    #[tokio::test]
+    async fn count() {
+        let volumes = Volumes::new();
+        assert_eq!(volumes.count().await, 0);
+
+        volumes
+            .create(VOLUME_NAME, Some(RawRepo::stub()))
+            .await
+            .unwrap();
+        volumes
+            .create("other", Some(RawRepo::stub()))
+            .await
+            .unwrap();
+
+        assert_eq!(volumes.count().await, 2);
+    }
+
+    #[tokio::test]
+    async fn filter_map_matches_read_all() {
+        let volumes = Volumes::new();
+        for name in [VOLUME_NAME, "other", "third"] {
+            volumes.create(name, Some(RawRepo::stub())).await.unwrap();
+        }
+
+        let mut all: Vec<String> = volumes
+            .read_all()
+            .await
+            .into_iter()
+            .map(|v| v.name)
+            .collect();
+        let mut names = volumes.filter_map(|v| Some(v.name.clone())).await;
+        all.sort();
+        names.sort();
+        assert_eq!(names, all);
+
+        let picked = volumes
+            .filter_map(|v| (v.name == "other").then(|| v.name.clone()))
+            .await;
+        assert_eq!(picked, ["other"]);
+    }
+
+    #[tokio::test]
+    async fn count_while_volume_locked() {
+        let volumes = Volumes::new();
+        let _locked = volumes
+            .create(VOLUME_NAME, Some(RawRepo::stub()))
+            .await
+            .unwrap();
+
+        assert_eq!(volumes.count().await, 1);
+    }
+
     #[rstest]
     #[case(" ", Some(RawRepo::stub()))]
     #[case(VOLUME_NAME, None)]
@@ -166,14 +459,177 @@ mod test {
         )
     }
 
+    #[rstest]
+    #[case::identical(RawRepo::stub(), true)]
+    #[case::same_url_spelling(RawRepo::from_url("https://EXAMPLE.com/repo"), true)]
+    #[case::other_branch(RawRepo { branch: Some("other".into()), ..RawRepo::stub() }, false)]
+    #[case::other_url(RawRepo::from_url("https://example.com/other.git"), false)]
+    #[tokio::test]
+    async fn create_again(#[case] second: RawRepo, #[case] accepted: bool) {
+        let volumes = Volumes::new();
+        let first = Repo::try_from(RawRepo::stub()).unwrap();
+        volumes
+            .create(VOLUME_NAME, Some(RawRepo::stub()))
+            .await
+            .unwrap();
+
+        let result = volumes
+            .create(VOLUME_NAME, Some(second))
+            .await
+            .map(|(_, created)| created);
+        if accepted {
+            assert!(!result.unwrap(), "A repeated create is not a new volume.");
+        } else {
+            let error = result.unwrap_err();
+            assert!(matches!(error, Error::AlreadyExists(_)), "{error:?}");
+        }
+
+        assert_eq!(volumes.count().await, 1);
+        assert_eq!(volumes.try_read(VOLUME_NAME).await.unwrap().repo, first);
+    }
+
+    #[tokio::test]
+    async fn create_again_restores_lost_auth() {
+        let volumes = Volumes::new();
+        let raw = || RawRepo {
+            token: Some("s3cr3t".into()),
+            ..RawRepo::stub()
+        };
+        {
+            let (mut volume, _) = volumes.create(VOLUME_NAME, Some(raw())).await.unwrap();
+            // As loaded from the state file after a restart.
+            volume.repo = volume.repo.masked_auth();
+            assert!(volume.repo.lost_auth());
+        }
+
+        let (volume, created) = volumes.create(VOLUME_NAME, Some(raw())).await.unwrap();
+
+        assert!(!created);
+        assert!(!volume.repo.lost_auth());
+        assert_eq!(volume.repo, Repo::try_from(raw()).unwrap());
+    }
+
+    #[tokio::test]
+    async fn update_replaces_repo_of_unused_volume() {
+        let volumes = Volumes::new();
+        {
+            let (mut volume, _) = volumes
+                .create(VOLUME_NAME, Some(RawRepo::stub()))
+                .await
+                .unwrap();
+            volume.path = Some(PathBuf::from("/tmp/data"));
+            volume.commit = Some("abc".into());
+            volume.status = Status::Cleared;
+        }
+        let branch = RawRepo {
+            branch: Some("release".into()),
+            ..RawRepo::stub()
+        };
+
+        let (previous, volume) = volumes.update(VOLUME_NAME, branch).await.unwrap();
+        assert_eq!(previous.repo, Repo::stub());
+        assert_eq!(previous.commit.as_deref(), Some("abc"));
+        assert_eq!(volume.repo.branch.as_deref(), Some("release"));
+        assert_eq!(volume.path, None);
+        assert_eq!(volume.commit, None);
+        assert!(matches!(volume.status, Status::Created));
+    }
+
+    #[tokio::test]
+    async fn update_refuses_used_volume() {
+        let volumes = Volumes::new();
+        volumes
+            .create(VOLUME_NAME, Some(RawRepo::stub()))
+            .await
+            .unwrap()
+            .0
+            .containers
+            .insert("id".into());
+
+        let error = volumes
+            .update(VOLUME_NAME, RawRepo::stub())
+            .await
+            .map(|_| ())
+            .unwrap_err();
+        assert!(matches!(error, Error::InUse(_)), "{error:?}");
+
+        let error = volumes
+            .update("missing", RawRepo::stub())
+            .await
+            .map(|_| ())
+            .unwrap_err();
+        assert!(matches!(error, Error::NonExists(_)), "{error:?}");
+    }
+
+    #[tokio::test]
+    async fn create_checks_host_policy() {
+        let volumes =
+            Volumes::new().with_host_policy(HostPolicy::new(vec!["example.com".into()], vec![]));
+
+        volumes
+            .create("allowed", Some(RawRepo::stub()))
+            .await
+            .unwrap();
+        let error = volumes
+            .create(
+                "denied",
+                Some(RawRepo::from_url("https://other.com/repo.git")),
+            )
+            .await
+            .unwrap_err();
+
+        assert!(matches!(error, Error::Url(_)), "{error:?}");
+        assert!(volumes.read("denied").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn create_survives_failed_save() {
+        let temp = tempfile::tempdir().unwrap();
+        let base_path = temp.path().join("base");
+        std::fs::create_dir(&base_path).unwrap();
+        let volumes = Volumes::load(&base_path).await.unwrap();
+        // A file in place of the base directory makes every save fail.
+        std::fs::remove_dir(&base_path).unwrap();
+        std::fs::write(&base_path, "").unwrap();
+
+        volumes
+            .create(VOLUME_NAME, Some(RawRepo::stub()))
+            .await
+            .unwrap();
+
+        assert!(volumes.read(VOLUME_NAME).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn create_up_to_limit() {
+        let volumes = Volumes::new().with_max_volumes(Some(2));
+
+        for name in ["first", "second"] {
+            volumes.create(name, Some(RawRepo::stub())).await.unwrap();
+        }
+        let error = volumes
+            .create("third", Some(RawRepo::stub()))
+            .await
+            .unwrap_err();
+        assert!(matches!(error, Error::LimitReached(2)), "{error:?}");
+        assert!(volumes.read("third").await.is_none());
+
+        volumes.remove("first").await.unwrap().unwrap();
+        volumes
+            .create("third", Some(RawRepo::stub()))
+            .await
+            .unwrap();
+    }
+
     #[tokio::test]
     async fn create_first_volume() {
         let volumes = Volumes::new();
-        let volume = volumes
+        let (volume, created) = volumes
             .create(VOLUME_NAME, Some(RawRepo::stub()))
             .await
             .unwrap();
 
+        assert!(created);
         assert_eq!(volume.name, VOLUME_NAME);
         assert_eq!(volume.repo.url.to_string(), REPO_URL);
         assert_eq!(volume.path, None);
@@ -198,7 +654,7 @@ mod test {
     #[tokio::test]
     async fn list_volumes() {
         let volumes = Volumes::new();
-        let created_volume = volumes
+        let (created_volume, _) = volumes
             .create(VOLUME_NAME, Some(RawRepo::stub()))
             .await
             .unwrap();
@@ -209,7 +665,7 @@ mod test {
         assert_eq!(list.len(), 1);
         assert!(list.contains(&first));
 
-        let second_volume = volumes
+        let (second_volume, _) = volumes
             .create("second_name", Some(RawRepo::stub()))
             .await
             .unwrap();
@@ -225,7 +681,7 @@ mod test {
     #[tokio::test]
     async fn remove_missing_volume() {
         let volumes = Volumes::new();
-        let volume = volumes.remove(VOLUME_NAME).await;
+        let volume = volumes.remove(VOLUME_NAME).await.unwrap();
 
         assert_eq!(volume, None)
     }
@@ -241,7 +697,7 @@ mod test {
         let list = volumes.read_all().await;
         assert_eq!(list.len(), 1);
 
-        let removed = volumes.remove(VOLUME_NAME).await;
+        let removed = volumes.remove(VOLUME_NAME).await.unwrap();
         assert!(removed.is_some());
         let removed = removed.unwrap();
         assert_eq!(removed.name, VOLUME_NAME);
@@ -250,6 +706,48 @@ mod test {
         assert_eq!(list.len(), 0);
     }
 
+    #[tokio::test]
+    async fn remove_mounted_volume() {
+        let volumes = Volumes::new();
+        volumes
+            .create(VOLUME_NAME, Some(RawRepo::stub()))
+            .await
+            .unwrap()
+            .0
+            .containers
+            .insert("id".into());
+
+        let error = volumes.remove(VOLUME_NAME).await.unwrap_err();
+
+        assert!(
+            matches!(&error, Error::Mounted { name, count: 1 } if name == VOLUME_NAME),
+            "{error:?}"
+        );
+        assert_eq!(volumes.count().await, 1);
+    }
+
+    #[tokio::test]
+    async fn prune_cleared_volumes() {
+        let volumes = Volumes::new();
+        for name in ["created", "cleared", "used", "locked"] {
+            let (mut volume, _) = volumes.create(name, Some(RawRepo::stub())).await.unwrap();
+            if name != "created" {
+                volume.status = Status::Cleared;
+            }
+            if name == "used" {
+                volume.containers.insert("id".into());
+            }
+        }
+        let _mounting = volumes.write("locked").await.unwrap();
+
+        let pruned = volumes.prune().await;
+
+        let pruned: Vec<_> = pruned.iter().map(|v| v.name.as_str()).collect();
+        assert_eq!(pruned, ["cleared"]);
+        assert!(volumes.read("cleared").await.is_none());
+        assert_eq!(volumes.read_map().await.len(), 3);
+    }
+
     #[tokio::test]
     async fn read_nonexistent_volume() {
         let volumes = Volumes::new();
@@ -264,8 +762,13 @@ mod test {
 
         let result1 = volumes.create(VOLUME_NAME, Some(RawRepo::stub())).await;
         assert!(result1.is_ok());
+        drop(result1);
 
-        let result2 = volumes.create(VOLUME_NAME, Some(RawRepo::stub())).await;
+        let other = RawRepo {
+            tag: Some("v1".into()),
+            ..RawRepo::stub()
+        };
+        let result2 = volumes.create(VOLUME_NAME, Some(other)).await;
         assert!(result2.is_err());
         let error = result2.unwrap_err();
         assert!(error.to_string().contains("already exists"));
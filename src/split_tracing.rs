@@ -1,13 +1,22 @@
 use std::io::{Result, Stderr, StderrLock, Stdout, StdoutLock, Write, stderr, stdout};
-use tracing::{Level, Metadata, level_filters::LevelFilter};
+use tracing::{Level, Metadata, Subscriber, level_filters::LevelFilter};
 use tracing_subscriber::{
     EnvFilter, Layer,
     fmt::{MakeWriter, layer},
     layer::SubscriberExt,
     registry,
+    registry::LookupSpan,
     util::SubscriberInitExt,
 };
 
+#[derive(Debug, Clone, Copy, Default, clap::ValueEnum)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    /// One JSON object per line, event fields under `fields`
+    Json,
+}
+
 enum StdioLock<'a> {
     Stdout(StdoutLock<'a>),
     Stderr(StderrLock<'a>),
@@ -61,18 +70,198 @@ impl<'a> MakeWriter<'a> for SplitMakeWriter {
     }
 }
 
-pub fn init() {
+fn fmt_layer<S, W>(format: LogFormat, writer: W) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    match format {
+        LogFormat::Text => layer()
+            .without_time()
+            .with_ansi(cfg!(debug_assertions))
+            .with_writer(writer)
+            .boxed(),
+        LogFormat::Json => layer().json().with_writer(writer).boxed(),
+    }
+}
+
+/// Dependencies only report problems unless asked otherwise.
+const QUIET_DEPENDENCIES: &str = "hyper=warn,hyper_util=warn,h2=warn,tower=warn,axum=info";
+
+/// Builds the filter from `--log-level`: either a bare level applied to gitvol, or
+/// comma-separated directives added to the defaults. `rust_log`, read from `RUST_LOG`,
+/// still wins when set.
+fn filter(log_level: &str, rust_log: Option<String>) -> EnvFilter {
+    let directives = if log_level.contains('=') {
+        format!("{QUIET_DEPENDENCIES},{log_level}")
+    } else {
+        format!("{QUIET_DEPENDENCIES},gitvol={log_level}")
+    };
+    EnvFilter::builder()
+        .with_default_directive(LevelFilter::INFO.into())
+        .parse_lossy(rust_log.unwrap_or(directives))
+}
+
+pub fn init(format: LogFormat, log_level: &str) {
     registry()
         .with(
-            layer()
-                .without_time()
-                .with_ansi(cfg!(debug_assertions))
-                .with_writer(SplitMakeWriter::new())
-                .with_filter(
-                    EnvFilter::builder()
-                        .with_default_directive(LevelFilter::DEBUG.into())
-                        .from_env_lossy(),
-                ),
+            fmt_layer(format, SplitMakeWriter::new())
+                .with_filter(filter(log_level, std::env::var("RUST_LOG").ok())),
         )
         .init()
 }
+
+#[cfg(test)]
+pub mod test_mocks {
+    use super::{LogFormat, filter, fmt_layer};
+    use std::{
+        io::{Result, Write},
+        sync::{Arc, Mutex},
+    };
+    use tracing::{Level, subscriber::DefaultGuard};
+    use tracing_subscriber::{
+        Layer,
+        fmt::{MakeWriter, format::FmtSpan},
+        layer::SubscriberExt,
+        registry,
+    };
+
+    /// Collects formatted events emitted on the current thread while the guard is alive.
+    #[derive(Clone, Default)]
+    pub struct LogCapture(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for LogCapture {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for LogCapture {
+        type Writer = Self;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    impl LogCapture {
+        pub fn start() -> (Self, DefaultGuard) {
+            let capture = Self::default();
+            let subscriber = tracing_subscriber::fmt()
+                .without_time()
+                .with_ansi(false)
+                .with_span_events(FmtSpan::NONE)
+                .with_max_level(Level::TRACE)
+                .with_writer(capture.clone())
+                .finish();
+            let guard = tracing::subscriber::set_default(subscriber);
+            (capture, guard)
+        }
+
+        /// Like `start`, but formats and filters events the way `init` does, `rust_log`
+        /// standing in for `RUST_LOG` so tests do not depend on the environment.
+        pub fn start_with(
+            format: LogFormat,
+            log_level: &str,
+            rust_log: Option<&str>,
+        ) -> (Self, DefaultGuard) {
+            let capture = Self::default();
+            let filter = filter(log_level, rust_log.map(str::to_string));
+            let subscriber =
+                registry().with(fmt_layer(format, capture.clone()).with_filter(filter));
+            let guard = tracing::subscriber::set_default(subscriber);
+            (capture, guard)
+        }
+
+        pub fn lines(&self) -> Vec<String> {
+            let buf = self.0.lock().unwrap();
+            String::from_utf8_lossy(&buf)
+                .lines()
+                .map(str::to_string)
+                .collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{LogFormat, test_mocks::LogCapture};
+    use rstest::rstest;
+    use serde_json::Value;
+    use tracing::{debug, info, info_span, trace, warn};
+
+    #[test]
+    fn json_lines() {
+        let (capture, _guard) = LogCapture::start_with(LogFormat::Json, "debug", None);
+
+        let span = info_span!("mount", id = "id-1");
+        let _entered = span.enter();
+        info!(
+            name = "volume",
+            url = "https://example.com/repo.git",
+            "Cloning"
+        );
+        warn!(name = "volume", "Refetch failed");
+
+        let lines: Vec<Value> = capture
+            .lines()
+            .iter()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["level"], "INFO");
+        assert_eq!(lines[0]["fields"]["message"], "Cloning");
+        assert_eq!(lines[0]["fields"]["name"], "volume");
+        assert_eq!(lines[0]["fields"]["url"], "https://example.com/repo.git");
+        assert_eq!(lines[0]["span"]["name"], "mount");
+        assert_eq!(lines[0]["span"]["id"], "id-1");
+        assert!(lines[0]["timestamp"].is_string());
+        assert_eq!(lines[1]["level"], "WARN");
+    }
+
+    #[test]
+    fn text_lines() {
+        let (capture, _guard) = LogCapture::start_with(LogFormat::Text, "debug", None);
+
+        info!(name = "volume", "Cloning");
+
+        let lines = capture.lines();
+        assert_eq!(lines.len(), 1);
+        assert!(serde_json::from_str::<Value>(&lines[0]).is_err());
+        assert!(lines[0].contains("Cloning"), "{}", lines[0]);
+        assert!(lines[0].contains("\"volume\""), "{}", lines[0]);
+    }
+
+    #[rstest]
+    #[case("debug", None, &["gitvol debug", "hyper warn"])]
+    #[case("info", None, &["hyper warn"])]
+    #[case("gitvol=trace,hyper=debug", None, &["gitvol trace", "gitvol debug", "hyper debug", "hyper warn"])]
+    #[case("debug", Some("trace"), &["gitvol trace", "gitvol debug", "hyper debug", "hyper warn"])]
+    fn filter_by_level(
+        #[case] log_level: &str,
+        #[case] rust_log: Option<&str>,
+        #[case] expect: &[&str],
+    ) {
+        let (capture, _guard) = LogCapture::start_with(LogFormat::Json, log_level, rust_log);
+
+        trace!("gitvol trace");
+        debug!("gitvol debug");
+        debug!(target: "hyper::proto", "hyper debug");
+        warn!(target: "hyper::proto", "hyper warn");
+
+        let messages: Vec<String> = capture
+            .lines()
+            .iter()
+            .map(|line| {
+                let line: Value = serde_json::from_str(line).unwrap();
+                line["fields"]["message"].as_str().unwrap().to_string()
+            })
+            .collect();
+        assert_eq!(messages, expect);
+    }
+}